@@ -11,7 +11,7 @@ use microkelvin::LinkError;
 
 use core::pin::Pin;
 use rkyv::{Archive, Serialize};
-use vm_proto::{Apply, Method};
+use vm_proto::{method, Apply, Method};
 
 #[derive(Archive, Serialize, Debug, Default)]
 pub struct FunLink {
@@ -32,6 +32,7 @@ impl Method for Push {
     type Return = ();
 }
 
+#[method]
 impl Apply<Push> for FunLink {
     fn apply(mut self: Pin<&mut Self>, arg: &Push) {
         self.list.push(arg.0)
@@ -46,6 +47,7 @@ impl Method for Pop {
     type Return = Result<Option<i32>, LinkError>;
 }
 
+#[method]
 impl Apply<Pop> for FunLink {
     fn apply(
         mut self: Pin<&mut Self>,
@@ -55,15 +57,3 @@ impl Apply<Pop> for FunLink {
         self.list.pop()
     }
 }
-
-// to autogenerate
-
-#[no_mangle]
-fn push(s: Pin<&mut FunLink>, t: &Push, r: &mut <Push as Method>::Return) {
-    *r = s.apply(t);
-}
-
-#[no_mangle]
-fn pop(s: Pin<&mut FunLink>, t: &Pop, r: &mut <Pop as Method>::Return) {
-    *r = s.apply(t);
-}
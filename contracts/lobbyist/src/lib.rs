@@ -0,0 +1,212 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+#![no_std]
+
+//! A contract calling into a deployed plutocracy, exercising calls between
+//! contracts.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::pin::Pin;
+
+use rkyv::{Archive, Serialize};
+use vm_proto::{abi, method, Apply, ContractId, Method, Query};
+
+/// The methods of the plutocracy contract called by the lobbyist.
+///
+/// They are mirrored rather than imported from the plutocracy crate, which
+/// would link its exports into this contract.
+pub mod plutocracy {
+    use rkyv::{Archive, Serialize};
+    use vm_proto::Method;
+
+    #[derive(Archive, Serialize, Debug)]
+    pub struct TotalSupply;
+
+    impl Method for TotalSupply {
+        const NAME: &'static str = "total_supply";
+        type Return = u64;
+    }
+
+    #[derive(Archive, Serialize, Debug)]
+    pub struct Mint {
+        pub amount: u64,
+    }
+
+    impl Method for Mint {
+        const NAME: &'static str = "mint";
+        type Return = ();
+    }
+}
+
+#[derive(Archive, Serialize, Debug, Default)]
+pub struct Lobbyist {
+    plutocracy: [u8; 32],
+}
+
+impl Lobbyist {
+    pub fn new(plutocracy: ContractId) -> Self {
+        Lobbyist {
+            plutocracy: *plutocracy.as_bytes(),
+        }
+    }
+}
+
+/// The total supply of the plutocracy.
+#[derive(Archive, Serialize, Debug)]
+pub struct Supply;
+
+impl Method for Supply {
+    const NAME: &'static str = "supply";
+    type Return = u64;
+}
+
+#[method]
+impl Query<Supply> for Lobbyist {
+    fn query(&self, _arg: &Supply) -> u64 {
+        let plutocracy = ContractId::from(self.plutocracy);
+        abi::query(plutocracy, &plutocracy::TotalSupply)
+    }
+}
+
+/// Has the plutocracy mint `amount`, returning the new total supply.
+#[derive(Archive, Serialize, Debug)]
+pub struct Lobby {
+    pub amount: u64,
+}
+
+impl Method for Lobby {
+    const NAME: &'static str = "lobby";
+    type Return = u64;
+}
+
+#[method]
+impl Apply<Lobby> for Lobbyist {
+    fn apply(self: Pin<&mut Self>, lobby: &Lobby) -> u64 {
+        let plutocracy = ContractId::from(self.plutocracy);
+        abi::apply(
+            plutocracy,
+            &plutocracy::Mint {
+                amount: lobby.amount,
+            },
+        );
+        abi::query(plutocracy, &plutocracy::TotalSupply)
+    }
+}
+
+/// The pitch made to the plutocracy for minting `amount`.
+#[derive(Archive, Serialize, Debug)]
+pub struct Pitch {
+    pub amount: u64,
+}
+
+impl Method for Pitch {
+    const NAME: &'static str = "pitch";
+    type Return = String;
+}
+
+#[method]
+impl Query<Pitch> for Lobbyist {
+    fn query(&self, pitch: &Pitch) -> String {
+        format!("please mint {}", pitch.amount)
+    }
+}
+
+/// Has the plutocracy mint `amount` once per round, returning the total
+/// supply after each round.
+#[derive(Archive, Serialize, Debug)]
+pub struct Campaign {
+    pub amount: u64,
+    pub rounds: u32,
+}
+
+impl Method for Campaign {
+    const NAME: &'static str = "campaign";
+    type Return = Vec<u64>;
+}
+
+#[method]
+impl Apply<Campaign> for Lobbyist {
+    fn apply(self: Pin<&mut Self>, campaign: &Campaign) -> Vec<u64> {
+        let plutocracy = ContractId::from(self.plutocracy);
+        (0..campaign.rounds)
+            .map(|_| {
+                abi::apply(
+                    plutocracy,
+                    &plutocracy::Mint {
+                        amount: campaign.amount,
+                    },
+                );
+                abi::query(plutocracy, &plutocracy::TotalSupply)
+            })
+            .collect()
+    }
+}
+
+/// Attempts to have the plutocracy mint from within a query, which the host
+/// rejects.
+#[derive(Archive, Serialize, Debug)]
+pub struct LobbyInQuery {
+    pub amount: u64,
+}
+
+impl Method for LobbyInQuery {
+    const NAME: &'static str = "lobby_in_query";
+    type Return = ();
+}
+
+#[method]
+impl Query<LobbyInQuery> for Lobbyist {
+    fn query(&self, lobby: &LobbyInQuery) {
+        let plutocracy = ContractId::from(self.plutocracy);
+        abi::apply(
+            plutocracy,
+            &plutocracy::Mint {
+                amount: lobby.amount,
+            },
+        )
+    }
+}
+
+/// Queries the supply through the given lobbyist, which the host rejects if
+/// it is the one already executing.
+#[derive(Archive, Serialize, Debug)]
+pub struct Reenter {
+    pub lobbyist: [u8; 32],
+}
+
+impl Method for Reenter {
+    const NAME: &'static str = "reenter";
+    type Return = u64;
+}
+
+#[method]
+impl Query<Reenter> for Lobbyist {
+    fn query(&self, reenter: &Reenter) -> u64 {
+        abi::query(ContractId::from(reenter.lobbyist), &Supply)
+    }
+}
+
+/// Attempts to bribe the plutocracy, which always panics.
+#[derive(Archive, Serialize, Debug)]
+pub struct Bribe {
+    pub amount: u64,
+}
+
+impl Method for Bribe {
+    const NAME: &'static str = "bribe";
+    type Return = ();
+}
+
+#[method]
+impl Apply<Bribe> for Lobbyist {
+    fn apply(self: Pin<&mut Self>, bribe: &Bribe) {
+        panic!("refused a bribe of {}", bribe.amount)
+    }
+}
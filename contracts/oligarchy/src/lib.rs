@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+#![no_std]
+
+//! The successor of the plutocracy contract, which it upgrades to by
+//! migrating its state.
+
+use rkyv::{Archive, Serialize};
+use vm_proto::{contract, write_migrated, Method, RETURN_BUFFER_SIZE};
+
+/// The largest treasury a plutocracy can be migrated with.
+pub const MAX_TREASURY: u64 = 1_000_000;
+
+/// The state of the plutocracy contract, as migrated from.
+///
+/// It is mirrored rather than imported from the plutocracy crate, which would
+/// link its exports into this contract.
+#[derive(Archive, Serialize, Debug, Default)]
+pub struct Plutocracy {
+    treasury: u64,
+}
+
+#[derive(Archive, Serialize, Debug, Default)]
+pub struct Oligarchy {
+    treasury: u64,
+    mints: u32,
+}
+
+impl Oligarchy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// Migrates the state of a plutocracy, failing if its treasury exceeds
+/// [`MAX_TREASURY`].
+#[no_mangle]
+fn migrate(state: &Plutocracy, ret: &mut [u8; RETURN_BUFFER_SIZE]) -> u32 {
+    assert!(
+        state.treasury <= MAX_TREASURY,
+        "treasury of {} is too large to migrate",
+        state.treasury
+    );
+
+    let migrated = Oligarchy {
+        treasury: state.treasury,
+        mints: 0,
+    };
+    write_migrated(&migrated, ret)
+}
+
+#[derive(Archive, Serialize, Debug)]
+pub struct TotalSupply;
+
+impl Method for TotalSupply {
+    const NAME: &'static str = "total_supply";
+    type Return = u64;
+}
+
+/// The number of mints since the migration.
+#[derive(Archive, Serialize, Debug)]
+pub struct Mints;
+
+impl Method for Mints {
+    const NAME: &'static str = "mints";
+    type Return = u32;
+}
+
+#[derive(Archive, Serialize, Debug)]
+pub struct Mint {
+    pub amount: u64,
+}
+
+impl Method for Mint {
+    const NAME: &'static str = "mint";
+    type Return = ();
+}
+
+#[contract]
+mod methods {
+    use core::pin::Pin;
+
+    use vm_proto::{Apply, Query};
+
+    use super::{Mint, Mints, Oligarchy, TotalSupply};
+
+    impl Query<TotalSupply> for Oligarchy {
+        fn query(&self, _arg: &TotalSupply) -> u64 {
+            self.treasury
+        }
+    }
+
+    impl Query<Mints> for Oligarchy {
+        fn query(&self, _arg: &Mints) -> u32 {
+            self.mints
+        }
+    }
+
+    impl Apply<Mint> for Oligarchy {
+        fn apply(mut self: Pin<&mut Self>, mint: &Mint) {
+            self.treasury += mint.amount;
+            self.mints += 1;
+        }
+    }
+}
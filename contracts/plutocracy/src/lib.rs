@@ -8,7 +8,7 @@
 use core::pin::Pin;
 
 use rkyv::{Archive, Serialize};
-use vm_proto::{Apply, Method, Query};
+use vm_proto::{abi, method, Apply, Method, Query};
 
 #[derive(Archive, Serialize, Debug, Default)]
 pub struct Plutocracy {
@@ -29,6 +29,7 @@ impl Method for TotalSupply {
     type Return = u64;
 }
 
+#[method]
 impl Query<TotalSupply> for Plutocracy {
     fn query(&self, _arg: &TotalSupply) -> u64 {
         self.treasury
@@ -45,24 +46,10 @@ impl Method for Mint {
     type Return = ();
 }
 
+#[method]
 impl Apply<Mint> for Plutocracy {
     fn apply(mut self: Pin<&mut Self>, mint: &Mint) {
-        self.treasury += mint.amount
+        self.treasury += mint.amount;
+        abi::emit("mint", &mint.amount);
     }
 }
-
-// to autogenerate
-
-#[no_mangle]
-fn total_supply(
-    s: &Plutocracy,
-    q: &TotalSupply,
-    r: &mut <TotalSupply as Method>::Return,
-) {
-    *r = s.query(q);
-}
-
-#[no_mangle]
-fn mint(s: Pin<&mut Plutocracy>, t: &Mint, r: &mut <Mint as Method>::Return) {
-    *r = s.apply(t);
-}
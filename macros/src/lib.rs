@@ -0,0 +1,284 @@
+//! Attribute macros generating the wasm exports of contract methods.
+//!
+//! Every `Query` or `Apply` implementation of a contract needs an exported
+//! function the host can call. The function is named after the method type in
+//! snake case, which must match the `NAME` of its `Method` implementation.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Error, GenericArgument, Ident, Item, ItemImpl, ItemMod, PathArguments, Type,
+};
+
+/// Generates the wasm export for a `Query` or `Apply` implementation.
+///
+/// ```ignore
+/// #[method]
+/// impl Query<TotalSupply> for Plutocracy {
+///     fn query(&self, _arg: &TotalSupply) -> u64 {
+///         self.treasury
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn method(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(Span::call_site(), "`#[method]` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let imp = parse_macro_input!(item as ItemImpl);
+
+    let export = match export(&imp) {
+        Ok(export) => export,
+        Err(err) => err.to_compile_error(),
+    };
+
+    quote!(#imp #export).into()
+}
+
+/// Generates the wasm exports for every `Query` and `Apply` implementation in
+/// an inline module.
+///
+/// ```ignore
+/// #[contract]
+/// mod plutocracy {
+///     impl Query<TotalSupply> for Plutocracy { ... }
+///     impl Apply<Mint> for Plutocracy { ... }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn contract(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(Span::call_site(), "`#[contract]` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let mut module = parse_macro_input!(item as ItemMod);
+
+    let items = match &mut module.content {
+        Some((_, items)) => items,
+        None => {
+            return Error::new(
+                module.span(),
+                "`#[contract]` must be applied to an inline module",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+
+    let exports = exports(items);
+    items.extend(exports.into_iter().map(Item::Verbatim));
+
+    quote!(#module).into()
+}
+
+/// The exports of the `Query` and `Apply` implementations among `items`,
+/// leaving out the ones marked with `#[method]`, which export themselves.
+fn exports(items: &[Item]) -> Vec<TokenStream2> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Impl(imp) if method_of(imp).is_some() && !is_method(imp) => Some(imp),
+            _ => None,
+        })
+        .map(|imp| export(imp).unwrap_or_else(|err| err.to_compile_error()))
+        .collect()
+}
+
+/// Whether `imp` carries the `#[method]` attribute.
+fn is_method(imp: &ItemImpl) -> bool {
+    imp.attrs.iter().any(|attr| {
+        attr.path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "method")
+    })
+}
+
+enum Kind {
+    Query,
+    Apply,
+}
+
+/// Returns the kind and method type of a `Query` or `Apply` implementation.
+fn method_of(imp: &ItemImpl) -> Option<(Kind, &Type)> {
+    let (_, path, _) = imp.trait_.as_ref()?;
+    let segment = path.segments.last()?;
+
+    let kind = match segment.ident.to_string().as_str() {
+        "Query" => Kind::Query,
+        "Apply" => Kind::Apply,
+        _ => return None,
+    };
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some((kind, ty)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn export(imp: &ItemImpl) -> syn::Result<TokenStream2> {
+    let (kind, method) = method_of(imp).ok_or_else(|| {
+        Error::new(
+            imp.span(),
+            "expected an implementation of `Query` or `Apply`",
+        )
+    })?;
+
+    if !imp.generics.params.is_empty() {
+        return Err(Error::new(
+            imp.generics.span(),
+            "contract methods cannot be generic",
+        ));
+    }
+
+    let name = match method {
+        Type::Path(path) => match path.path.segments.last() {
+            Some(segment) => snake_case(&segment.ident.to_string()),
+            None => return Err(Error::new(method.span(), "expected a method type")),
+        },
+        _ => return Err(Error::new(method.span(), "expected a method type")),
+    };
+
+    let ident = export_ident(&name, method)?;
+    let contract = &imp.self_ty;
+
+    let function = match kind {
+        Kind::Query => quote! {
+            #[no_mangle]
+            fn #ident(
+                state: &#contract,
+                arg: &#method,
+                ret: &mut [u8; vm_proto::RETURN_BUFFER_SIZE],
+            ) -> u32 {
+                vm_proto::write_return(
+                    &<#contract as vm_proto::Query<#method>>::query(state, arg),
+                    ret,
+                )
+            }
+        },
+        Kind::Apply => quote! {
+            #[no_mangle]
+            fn #ident(
+                state: core::pin::Pin<&mut #contract>,
+                arg: &#method,
+                ret: &mut [u8; vm_proto::RETURN_BUFFER_SIZE],
+            ) -> u32 {
+                vm_proto::write_return(
+                    &<#contract as vm_proto::Apply<#method>>::apply(state, arg),
+                    ret,
+                )
+            }
+        },
+    };
+
+    Ok(quote! {
+        const _: () = assert!(
+            vm_proto::names_match(<#method as vm_proto::Method>::NAME, #name),
+            concat!("the `NAME` of the method must be \"", #name, "\""),
+        );
+
+        #function
+    })
+}
+
+/// The identifier of the export named `name`, which is raw if `name` is a
+/// keyword, as for a method type named `Move`.
+fn export_ident(name: &str, method: &Type) -> syn::Result<Ident> {
+    if syn::parse_str::<Ident>(name).is_ok() {
+        return Ok(Ident::new(name, method.span()));
+    }
+
+    match name {
+        // keywords that cannot be raw identifiers
+        "crate" | "self" | "super" => Err(Error::new(
+            method.span(),
+            format!("a method type cannot be named after the keyword `{}`", name),
+        )),
+        _ => Ok(Ident::new_raw(name, method.span())),
+    }
+}
+
+/// Converts a type name such as `TotalSupply` to `total_supply`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+
+    snake
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export, exports, snake_case};
+
+    #[test]
+    fn snake_case_names() {
+        assert_eq!(snake_case("Mint"), "mint");
+        assert_eq!(snake_case("TotalSupply"), "total_supply");
+        assert_eq!(snake_case("HTTPRequest"), "http_request");
+        assert_eq!(snake_case("Push2Front"), "push2_front");
+    }
+
+    #[test]
+    fn keyword_names() {
+        let imp = syn::parse_quote! {
+            impl Apply<Move> for Pieces {
+                fn apply(self: Pin<&mut Self>, _arg: &Move) {}
+            }
+        };
+        let tokens = export(&imp).expect("a raw identifier").to_string();
+        assert!(tokens.contains("r#move"), "{}", tokens);
+
+        let imp = syn::parse_quote! {
+            impl Query<Super> for Pieces {
+                fn query(&self, _arg: &Super) {}
+            }
+        };
+        assert!(export(&imp).is_err());
+    }
+    #[test]
+    fn methods_are_exported_once() {
+        let module: syn::ItemMod = syn::parse_quote! {
+            mod pieces {
+                impl Apply<Move> for Pieces {
+                    fn apply(self: Pin<&mut Self>, _arg: &Move) {}
+                }
+
+                #[method]
+                impl Query<Count> for Pieces {
+                    fn query(&self, _arg: &Count) -> u32 { 0 }
+                }
+
+                impl Pieces {
+                    fn new() -> Self { Pieces }
+                }
+            }
+        };
+        let (_, items) = module.content.expect("an inline module");
+
+        let exports = exports(&items);
+        assert_eq!(exports.len(), 1);
+        assert!(exports[0].to_string().contains("r#move"), "{}", exports[0]);
+    }
+}
@@ -1,8 +1,88 @@
 #[cfg(not(feature = "host"))]
-mod ext {
+use rkyv::check_archived_root;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::AlignedBytes;
+use rkyv::{Archive, CheckBytes, Deserialize, Infallible, Serialize};
+
+#[cfg(feature = "host")]
+use crate::definitions::BlockContext;
+use crate::definitions::{
+    serialize_into, Caller, ContractId, ContractSerializer, Method, BLS_PUBLIC_KEY_SIZE,
+    BLS_SIGNATURE_SIZE, RETURN_BUFFER_SIZE, SCALAR_SIZE, SCHNORR_PUBLIC_KEY_SIZE,
+    SCHNORR_SIGNATURE_SIZE,
+};
+#[cfg(not(feature = "host"))]
+use crate::definitions::{ACCOUNT_CALLER, CONTRACT_CALLER, NO_CALLER};
+#[cfg(feature = "host")]
+use crate::host::Event;
+
+#[cfg(not(feature = "host"))]
+pub(crate) mod ext {
     #[link(wasm_import_module = "env")]
     extern "C" {
         pub fn debug(ofs: &u8, len: i32);
+
+        pub fn panic(
+            msg: *const u8,
+            msg_len: i32,
+            file: *const u8,
+            file_len: i32,
+            line: i32,
+            column: i32,
+        );
+
+        pub fn emit(topic: *const u8, topic_len: i32, data: *const u8, data_len: i32);
+
+        pub fn caller(id: *mut u8) -> i32;
+
+        pub fn balance() -> i64;
+
+        pub fn value() -> i64;
+
+        pub fn transfer(kind: i32, to: *const u8, amount: i64);
+
+        pub fn blake2b(data: *const u8, data_len: i32, hash: *mut u8);
+
+        pub fn poseidon(inputs: *const u8, inputs_len: i32, hash: *mut u8);
+
+        pub fn verify_schnorr(
+            public_key: *const u8,
+            message: *const u8,
+            signature: *const u8,
+        ) -> i32;
+
+        pub fn verify_bls(
+            public_key: *const u8,
+            message: *const u8,
+            message_len: i32,
+            signature: *const u8,
+        ) -> i32;
+
+        pub fn block_height() -> i64;
+
+        pub fn block_timestamp() -> i64;
+
+        pub fn chain_id() -> i64;
+
+        pub fn query(
+            id: &u8,
+            name: *const u8,
+            name_len: i32,
+            arg: *const u8,
+            arg_len: i32,
+            arg_root: i32,
+            ret: *mut u8,
+        ) -> i32;
+
+        pub fn apply(
+            id: &u8,
+            name: *const u8,
+            name_len: i32,
+            arg: *const u8,
+            arg_len: i32,
+            arg_root: i32,
+            ret: *mut u8,
+        ) -> i32;
     }
 }
 
@@ -12,9 +92,363 @@ pub fn debug(string: &'static str) {
     unsafe { ext::debug(&bytes[0], bytes.len() as i32) }
 }
 
+/// Emits an event under `topic`, to be collected by the host in the receipt of
+/// the call.
+#[cfg(not(feature = "host"))]
+pub fn emit<E>(topic: &str, event: &E)
+where
+    E: for<'a> Serialize<ContractSerializer<'a>>,
+{
+    let mut buf = AlignedBytes::<RETURN_BUFFER_SIZE>::default();
+    let (_, len) = serialize_into(event, &mut buf[..]);
+
+    let topic = topic.as_bytes();
+    unsafe { ext::emit(topic.as_ptr(), topic.len() as i32, buf.as_ptr(), len as i32) }
+}
+
+/// Who invoked the contract, or `None` for a query made outside of any
+/// transaction.
+#[cfg(not(feature = "host"))]
+pub fn caller() -> Option<Caller> {
+    let mut bytes = [0u8; 32];
+    match unsafe { ext::caller(bytes.as_mut_ptr()) } {
+        ACCOUNT_CALLER => Some(Caller::Account(bytes)),
+        CONTRACT_CALLER => Some(Caller::Contract(ContractId::from(bytes))),
+        NO_CALLER => None,
+        _ => unreachable!("unknown kind of caller"),
+    }
+}
+
+/// The native balance of the contract.
+#[cfg(not(feature = "host"))]
+pub fn balance() -> u64 {
+    unsafe { ext::balance() as u64 }
+}
+
+/// The value attached to the transaction, already added to the balance of the
+/// contract.
+///
+/// Only the contract a transaction is applied to receives a value, so this is
+/// zero in calls from other contracts.
+#[cfg(not(feature = "host"))]
+pub fn value() -> u64 {
+    unsafe { ext::value() as u64 }
+}
+
+/// Transfers `amount` from the balance of the contract to an account or
+/// another contract.
+///
+/// The transaction fails if the balance is insufficient, or if called from a
+/// query.
+#[cfg(not(feature = "host"))]
+pub fn transfer(to: Caller, amount: u64) {
+    let (kind, bytes) = match to {
+        Caller::Account(account) => (ACCOUNT_CALLER, account),
+        Caller::Contract(id) => (CONTRACT_CALLER, *id.as_bytes()),
+    };
+    unsafe { ext::transfer(kind, bytes.as_ptr(), amount as i64) }
+}
+
+/// The 32 byte Blake2b hash of `data`, computed by the host.
+#[cfg(not(feature = "host"))]
+pub fn blake2b(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    unsafe { ext::blake2b(data.as_ptr(), data.len() as i32, hash.as_mut_ptr()) };
+    hash
+}
+
+/// The Poseidon hash of a sequence of serialized BLS12-381 scalars, computed
+/// by the host.
+///
+/// The transaction fails if any of the inputs is not a valid scalar.
+#[cfg(not(feature = "host"))]
+pub fn poseidon(inputs: &[[u8; SCALAR_SIZE]]) -> [u8; SCALAR_SIZE] {
+    let mut hash = [0u8; SCALAR_SIZE];
+    unsafe {
+        ext::poseidon(
+            inputs.as_ptr() as *const u8,
+            inputs.len() as i32,
+            hash.as_mut_ptr(),
+        )
+    };
+    hash
+}
+
+/// Verifies a Schnorr signature over a scalar message.
+#[cfg(not(feature = "host"))]
+pub fn verify_schnorr(
+    public_key: &[u8; SCHNORR_PUBLIC_KEY_SIZE],
+    message: &[u8; SCALAR_SIZE],
+    signature: &[u8; SCHNORR_SIGNATURE_SIZE],
+) -> bool {
+    unsafe { ext::verify_schnorr(public_key.as_ptr(), message.as_ptr(), signature.as_ptr()) == 1 }
+}
+
+/// Verifies a BLS signature over `message`.
+#[cfg(not(feature = "host"))]
+pub fn verify_bls(
+    public_key: &[u8; BLS_PUBLIC_KEY_SIZE],
+    message: &[u8],
+    signature: &[u8; BLS_SIGNATURE_SIZE],
+) -> bool {
+    unsafe {
+        ext::verify_bls(
+            public_key.as_ptr(),
+            message.as_ptr(),
+            message.len() as i32,
+            signature.as_ptr(),
+        ) == 1
+    }
+}
+
+/// The height of the block the transaction is applied in.
+#[cfg(not(feature = "host"))]
+pub fn block_height() -> u64 {
+    unsafe { ext::block_height() as u64 }
+}
+
+/// The timestamp of the block the transaction is applied in.
+#[cfg(not(feature = "host"))]
+pub fn block_timestamp() -> u64 {
+    unsafe { ext::block_timestamp() as u64 }
+}
+
+/// The id of the chain the transaction is applied on.
+#[cfg(not(feature = "host"))]
+pub fn chain_id() -> u64 {
+    unsafe { ext::chain_id() as u64 }
+}
+
+/// Queries another contract, returning the result.
+///
+/// Panics if the other contract returns an invalid value.
+#[cfg(not(feature = "host"))]
+pub fn query<M>(id: ContractId, arg: &M) -> M::Return
+where
+    M: Method + for<'a> Serialize<ContractSerializer<'a>>,
+    M::Return: Archive,
+    <M::Return as Archive>::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<M::Return, Infallible>,
+{
+    call(ext::query, id, arg)
+}
+
+/// Applies a transaction to another contract, returning the result.
+///
+/// Panics if the other contract returns an invalid value.
+#[cfg(not(feature = "host"))]
+pub fn apply<M>(id: ContractId, arg: &M) -> M::Return
+where
+    M: Method + for<'a> Serialize<ContractSerializer<'a>>,
+    M::Return: Archive,
+    <M::Return as Archive>::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<M::Return, Infallible>,
+{
+    call(ext::apply, id, arg)
+}
+
+#[cfg(not(feature = "host"))]
+type CallImport = unsafe extern "C" fn(&u8, *const u8, i32, *const u8, i32, i32, *mut u8) -> i32;
+
+#[cfg(not(feature = "host"))]
+fn call<M>(import: CallImport, id: ContractId, arg: &M) -> M::Return
+where
+    M: Method + for<'a> Serialize<ContractSerializer<'a>>,
+    M::Return: Archive,
+    <M::Return as Archive>::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<M::Return, Infallible>,
+{
+    // The same buffer holds the argument and, once the call returns, the
+    // archived return value.
+    let mut buf = AlignedBytes::<RETURN_BUFFER_SIZE>::default();
+    let (arg_root, arg_len) = serialize_into(arg, &mut buf[..]);
+
+    let name = M::NAME.as_bytes();
+
+    let ret_len = unsafe {
+        import(
+            &id.as_bytes()[0],
+            name.as_ptr(),
+            name.len() as i32,
+            buf.as_ptr(),
+            arg_len as i32,
+            arg_root as i32,
+            buf.as_mut_ptr(),
+        )
+    };
+
+    // The return value is written by the callee, which is not to be trusted
+    let archived = check_archived_root::<M::Return>(&buf[..ret_len as usize])
+        .unwrap_or_else(|_| panic!("Invalid return value from {}", M::NAME));
+    archived.deserialize(&mut Infallible).expect("Infallible")
+}
+
 // Host mockups of the ABI
 
 #[cfg(feature = "host")]
 pub fn debug(string: &'static str) {
     println!("HOST DEBUG: {}", string)
 }
+
+// The events emitted by the native contract, separate for each thread so
+// concurrently running tests do not see each other's events.
+#[cfg(feature = "host")]
+thread_local! {
+    static EVENTS: std::cell::RefCell<Vec<Event>> = Default::default();
+}
+
+#[cfg(feature = "host")]
+pub fn emit<E>(topic: &str, event: &E)
+where
+    E: for<'a> Serialize<ContractSerializer<'a>>,
+{
+    let mut buf = AlignedBytes::<RETURN_BUFFER_SIZE>::default();
+    let (_, len) = serialize_into(event, &mut buf[..]);
+
+    // the native contract has no id, so its events have the default source
+    let event = Event::new(ContractId::default(), topic.into(), buf[..len].to_vec());
+    EVENTS.with(|events| events.borrow_mut().push(event));
+}
+
+/// Takes the events emitted by the native contract on the current thread,
+/// oldest first.
+#[cfg(feature = "host")]
+pub fn take_events() -> Vec<Event> {
+    EVENTS.with(|events| std::mem::take(&mut *events.borrow_mut()))
+}
+
+// The caller seen by the native contract, set by tests to the one they
+// simulate.
+#[cfg(feature = "host")]
+thread_local! {
+    static CALLER: std::cell::Cell<Option<Caller>> = Default::default();
+}
+
+/// Sets the caller seen by the native contract on the current thread.
+#[cfg(feature = "host")]
+pub fn set_caller(caller: Option<Caller>) {
+    CALLER.with(|cell| cell.set(caller))
+}
+
+#[cfg(feature = "host")]
+pub fn caller() -> Option<Caller> {
+    CALLER.with(|cell| cell.get())
+}
+
+// The balance of the native contract and the value attached to the
+// transaction it executes, set by tests to the ones they simulate.
+#[cfg(feature = "host")]
+thread_local! {
+    static BALANCE: std::cell::Cell<u64> = Default::default();
+    static VALUE: std::cell::Cell<u64> = Default::default();
+}
+
+/// Sets the balance of the native contract on the current thread.
+#[cfg(feature = "host")]
+pub fn set_balance(balance: u64) {
+    BALANCE.with(|cell| cell.set(balance))
+}
+
+/// Sets the value attached to the transaction executed by the native contract
+/// on the current thread.
+#[cfg(feature = "host")]
+pub fn set_value(value: u64) {
+    VALUE.with(|cell| cell.set(value))
+}
+
+#[cfg(feature = "host")]
+pub fn balance() -> u64 {
+    BALANCE.with(|cell| cell.get())
+}
+
+#[cfg(feature = "host")]
+pub fn value() -> u64 {
+    VALUE.with(|cell| cell.get())
+}
+
+#[cfg(feature = "host")]
+pub fn transfer(_to: Caller, amount: u64) {
+    BALANCE.with(|cell| {
+        let balance = cell.get();
+        assert!(balance >= amount, "insufficient balance");
+        cell.set(balance - amount)
+    })
+}
+
+#[cfg(feature = "host")]
+pub fn blake2b(data: &[u8]) -> [u8; 32] {
+    crate::host::blake2b(data)
+}
+
+#[cfg(feature = "host")]
+pub fn poseidon(inputs: &[[u8; SCALAR_SIZE]]) -> [u8; SCALAR_SIZE] {
+    crate::host::poseidon(inputs).expect("inputs are valid scalars")
+}
+
+#[cfg(feature = "host")]
+pub fn verify_schnorr(
+    public_key: &[u8; SCHNORR_PUBLIC_KEY_SIZE],
+    message: &[u8; SCALAR_SIZE],
+    signature: &[u8; SCHNORR_SIGNATURE_SIZE],
+) -> bool {
+    crate::host::verify_schnorr(public_key, message, signature)
+}
+
+#[cfg(feature = "host")]
+pub fn verify_bls(
+    public_key: &[u8; BLS_PUBLIC_KEY_SIZE],
+    message: &[u8],
+    signature: &[u8; BLS_SIGNATURE_SIZE],
+) -> bool {
+    crate::host::verify_bls(public_key, message, signature)
+}
+
+// The block seen by the native contract, set by tests to the one they
+// simulate.
+#[cfg(feature = "host")]
+thread_local! {
+    static BLOCK: std::cell::Cell<BlockContext> = Default::default();
+}
+
+/// Sets the block seen by the native contract on the current thread.
+#[cfg(feature = "host")]
+pub fn set_block(block: BlockContext) {
+    BLOCK.with(|cell| cell.set(block))
+}
+
+#[cfg(feature = "host")]
+pub fn block_height() -> u64 {
+    BLOCK.with(|cell| cell.get().height)
+}
+
+#[cfg(feature = "host")]
+pub fn block_timestamp() -> u64 {
+    BLOCK.with(|cell| cell.get().timestamp)
+}
+
+#[cfg(feature = "host")]
+pub fn chain_id() -> u64 {
+    BLOCK.with(|cell| cell.get().chain_id)
+}
+
+#[cfg(feature = "host")]
+pub fn query<M>(_id: ContractId, _arg: &M) -> M::Return
+where
+    M: Method + for<'a> Serialize<ContractSerializer<'a>>,
+    M::Return: Archive,
+    <M::Return as Archive>::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<M::Return, Infallible>,
+{
+    panic!("Cross-contract calls are only available to deployed contracts")
+}
+
+#[cfg(feature = "host")]
+pub fn apply<M>(_id: ContractId, _arg: &M) -> M::Return
+where
+    M: Method + for<'a> Serialize<ContractSerializer<'a>>,
+    M::Return: Archive,
+    <M::Return as Archive>::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<M::Return, Infallible>,
+{
+    panic!("Cross-contract calls are only available to deployed contracts")
+}
@@ -0,0 +1,250 @@
+//! Deploys and calls contracts in a state persisted to a directory.
+//!
+//! Run without arguments for usage.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::process;
+
+use vm_proto::{BlockContext, Caller, ContractId, Permissions, Receipt, State};
+
+const USAGE: &str = "\
+usage: vm-proto <dir> <command>
+
+commands:
+    deploy <code.wasm> <state> [--root N] [--allow MODULE[.NAME]]...
+        deploy a contract with an archived initial state, only allowed to
+        import the given modules and functions if any are given
+    list
+        list the deployed contracts, along with their balances
+    deposit <account> <amount>
+        credit an account with native tokens
+    balance <account>
+        print the balance of an account
+    query <id> <method> [<arg>] [--root N] [--gas N]
+    apply <id> <method> [<arg>] [--root N] [--gas N]
+          [--caller ACCOUNT] [--value N]
+          [--height N] [--timestamp N] [--chain-id N]
+        call a method with an archived argument, printing the archived
+        return value in hex
+
+arguments and states are read as hex, or from a file when prefixed with `@`.
+--root gives the position of their archived root, 0 by default.";
+
+const DEFAULT_GAS_LIMIT: u64 = 1_000_000;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(e) = run(&args) {
+        if e.is::<Usage>() {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// The arguments do not match any command.
+#[derive(Debug)]
+struct Usage;
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(USAGE)
+    }
+}
+
+impl Error for Usage {}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (positional, options) = parse(args)?;
+
+    let (dir, command, rest) = match positional.as_slice() {
+        [dir, command, rest @ ..] => (dir, command.as_str(), rest),
+        _ => return Err(Usage.into()),
+    };
+
+    let root = options.root.unwrap_or(0);
+    let gas_limit = options.gas.unwrap_or(DEFAULT_GAS_LIMIT);
+
+    let mut state = State::open(dir)?;
+
+    match (command, rest) {
+        ("deploy", [code, initial]) => {
+            let code = fs::read(code)?;
+            let initial = bytes(initial)?;
+
+            let permissions = options.permissions.unwrap_or_default();
+
+            let id = state.deploy_raw(&initial, root, code, permissions)?;
+            state.persist()?;
+
+            println!("{}", hex(id.as_bytes()));
+        }
+        ("list", []) => {
+            let mut ids: Vec<_> = state.contracts().collect();
+            ids.sort();
+
+            for id in ids {
+                let balance = state.balance(&Caller::Contract(*id));
+                println!("{} {}", hex(id.as_bytes()), balance);
+            }
+        }
+        ("deposit", [account, amount]) => {
+            let account = Caller::Account(bytes32(&unhex(account)?)?);
+            let amount = amount.parse()?;
+
+            state.deposit(account, amount)?;
+            state.persist()?;
+        }
+        ("balance", [account]) => {
+            let account = Caller::Account(bytes32(&unhex(account)?)?);
+            println!("{}", state.balance(&account));
+        }
+        ("query", [id, method, arg @ ..]) if arg.len() <= 1 => {
+            let arg = arg.first().map(|arg| bytes(arg)).transpose()?;
+            let arg = arg.unwrap_or_default();
+
+            let receipt = state.query_raw(contract_id(id)?, method, &arg, root, gas_limit)?;
+            print_receipt(&receipt);
+        }
+        ("apply", [id, method, arg @ ..]) if arg.len() <= 1 => {
+            let arg = arg.first().map(|arg| bytes(arg)).transpose()?;
+            let arg = arg.unwrap_or_default();
+
+            let caller = Caller::Account(options.caller.unwrap_or_default());
+
+            let receipt = state.apply_raw(
+                contract_id(id)?,
+                method,
+                &arg,
+                root,
+                caller,
+                options.value,
+                options.block,
+                gas_limit,
+            )?;
+            state.persist()?;
+
+            print_receipt(&receipt);
+        }
+        _ => return Err(Usage.into()),
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    root: Option<usize>,
+    gas: Option<u64>,
+    caller: Option<[u8; 32]>,
+    value: u64,
+    block: BlockContext,
+    permissions: Option<Permissions>,
+}
+
+/// Splits the arguments into positional ones and options.
+fn parse(args: &[String]) -> Result<(Vec<String>, Options), Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut options = Options::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => options.root = Some(value(args.next(), arg)?),
+            "--gas" => options.gas = Some(value(args.next(), arg)?),
+            "--caller" => {
+                let account: String = value(args.next(), arg)?;
+                options.caller = Some(bytes32(&unhex(&account)?)?);
+            }
+            "--value" => options.value = value(args.next(), arg)?,
+            "--height" => options.block.height = value(args.next(), arg)?,
+            "--timestamp" => options.block.timestamp = value(args.next(), arg)?,
+            "--chain-id" => options.block.chain_id = value(args.next(), arg)?,
+            "--allow" => {
+                let import: String = value(args.next(), arg)?;
+                let permissions = options.permissions.take().unwrap_or_else(Permissions::none);
+
+                options.permissions = Some(match import.split_once('.') {
+                    Some((module, name)) => permissions.allow_function(module, name),
+                    None => permissions.allow_module(&import),
+                });
+            }
+            "-h" | "--help" => return Err(Usage.into()),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    Ok((positional, options))
+}
+
+fn value<T>(value: Option<&String>, option: &str) -> Result<T, Box<dyn Error>>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("{} needs a value", option))?;
+    value
+        .parse()
+        .map_err(|e| format!("invalid value {:?} for {}: {}", value, option, e).into())
+}
+
+fn print_receipt(receipt: &Receipt<rkyv::AlignedVec>) {
+    println!("{}", hex(receipt));
+    eprintln!("gas used: {}", receipt.gas_used());
+
+    for event in receipt.events() {
+        eprintln!(
+            "event {} from {}: {}",
+            event.topic(),
+            hex(event.source().as_bytes()),
+            hex(event.data())
+        );
+    }
+}
+
+/// Reads bytes given in hex, or from the file named after an `@`.
+fn bytes(arg: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    match arg.strip_prefix('@') {
+        Some(path) => Ok(fs::read(path)?),
+        None => unhex(arg),
+    }
+}
+
+fn contract_id(arg: &str) -> Result<ContractId, Box<dyn Error>> {
+    Ok(ContractId::from(bytes32(&unhex(arg)?)?))
+}
+
+fn bytes32(bytes: &[u8]) -> Result<[u8; 32], Box<dyn Error>> {
+    let mut array = [0u8; 32];
+
+    if bytes.len() != array.len() {
+        return Err(format!("expected 32 bytes, got {}", bytes.len()).into());
+    }
+    array.copy_from_slice(bytes);
+
+    Ok(array)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        return Err(format!("invalid hex {:?}", hex).into());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
@@ -1,8 +1,82 @@
 use core::{fmt::Debug, pin::Pin};
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+use rkyv::ser::serializers::{BufferScratch, BufferSerializer, CompositeSerializer};
+use rkyv::ser::Serializer;
+use rkyv::{AlignedBytes, Infallible, Serialize};
+
+/// The size of the buffer reserved by the host for the archived return value
+/// of a call.
+pub const RETURN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The size of the largest archived argument a call may carry, matching the
+/// buffer contracts archive arguments into.
+pub const MAX_ARGUMENT_SIZE: usize = RETURN_BUFFER_SIZE;
+
+/// The amount of scratch space available when serializing inside a contract.
+pub const SCRATCH_SIZE: usize = 1024;
+
+/// Serializer used by contracts, which cannot count on allocations, to write
+/// archived values into a buffer.
+pub type ContractSerializer<'a> = CompositeSerializer<
+    BufferSerializer<&'a mut [u8]>,
+    BufferScratch<AlignedBytes<SCRATCH_SIZE>>,
+    Infallible,
+>;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
 pub struct ContractId([u8; 32]);
 
+impl ContractId {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for ContractId {
+    fn from(bytes: [u8; 32]) -> Self {
+        ContractId(bytes)
+    }
+}
+
+/// Who invoked a contract, also identifying the owners of native balances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Caller {
+    /// An account outside the state, such as the sender of a transaction.
+    Account([u8; 32]),
+    /// A contract calling another in the course of its own execution.
+    Contract(ContractId),
+}
+
+// How the `caller` and `transfer` imports encode the kind of caller, passing
+// its bytes alongside.
+pub(crate) const NO_CALLER: i32 = 0;
+pub(crate) const ACCOUNT_CALLER: i32 = 1;
+pub(crate) const CONTRACT_CALLER: i32 = 2;
+
+/// The size of a serialized BLS12-381 scalar, the input and output of the
+/// Poseidon hash.
+pub const SCALAR_SIZE: usize = 32;
+
+/// The size of a serialized Schnorr public key.
+pub const SCHNORR_PUBLIC_KEY_SIZE: usize = 32;
+
+/// The size of a serialized Schnorr signature.
+pub const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+/// The size of a serialized BLS public key.
+pub const BLS_PUBLIC_KEY_SIZE: usize = 96;
+
+/// The size of a serialized BLS signature.
+pub const BLS_SIGNATURE_SIZE: usize = 48;
+
+/// The block a transaction is applied in, as seen by contracts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockContext {
+    pub height: u64,
+    pub timestamp: u64,
+    pub chain_id: u64,
+}
+
 pub trait Method {
     const NAME: &'static str;
     type Return;
@@ -15,3 +89,78 @@ pub trait Query<Q: Method> {
 pub trait Apply<T: Method> {
     fn apply(self: Pin<&mut Self>, t: &T) -> T::Return;
 }
+
+/// Writes the archived return value of a call into the buffer reserved by the
+/// host, returning the number of bytes written.
+///
+/// The archived root is placed at the end of the written bytes, so the host
+/// can access it regardless of the size of the return type.
+pub fn write_return<R>(ret: &R, buf: &mut [u8; RETURN_BUFFER_SIZE]) -> u32
+where
+    R: for<'a> Serialize<ContractSerializer<'a>>,
+{
+    let (_, len) = serialize_into(ret, &mut buf[..]);
+    len as u32
+}
+
+/// The export called to migrate the state of a contract when its code is
+/// upgraded.
+///
+/// It receives the archived state left by the old code and a return buffer,
+/// and should write the new state with [`write_migrated`], returning the
+/// number of bytes written.
+pub const MIGRATE_EXPORT: &str = "migrate";
+
+/// Writes the migrated state of a contract into the buffer reserved by the
+/// host, returning the number of bytes written.
+///
+/// Since the host needs to know where the root of the new state is, its
+/// position is appended to the archive as a little endian `u32`.
+pub fn write_migrated<S>(state: &S, buf: &mut [u8; RETURN_BUFFER_SIZE]) -> u32
+where
+    S: for<'a> Serialize<ContractSerializer<'a>>,
+{
+    let (root, len) = serialize_into(state, &mut buf[..]);
+
+    let root = (root as u32).to_le_bytes();
+    buf.get_mut(len..len + root.len())
+        .expect("migrated state exceeds the return buffer")
+        .copy_from_slice(&root);
+
+    (len + root.len()) as u32
+}
+
+/// Serializes `value` into `buf`, returning the position of the archived root
+/// and the number of bytes written.
+pub(crate) fn serialize_into<T>(value: &T, buf: &mut [u8]) -> (usize, usize)
+where
+    T: for<'a> Serialize<ContractSerializer<'a>>,
+{
+    let mut serialize =
+        ContractSerializer::new(BufferSerializer::new(buf), Default::default(), Infallible);
+    let root = serialize
+        .serialize_value(value)
+        .expect("value exceeds the serialization buffer");
+    (root, serialize.pos())
+}
+
+/// Compares method names at compile time, used by the generated exports to
+/// check they are named after `Method::NAME`.
+#[doc(hidden)]
+pub const fn names_match(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
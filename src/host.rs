@@ -1,10 +1,40 @@
-use std::collections::HashMap as Map;
+use std::collections::{BTreeMap, HashMap as Map};
 use std::fmt::{Debug, Display};
 use std::io;
 use std::mem;
+use std::path::PathBuf;
 
 use crate::definitions::*;
 
+mod backend;
+mod builder;
+mod cache;
+mod crypto;
+mod env;
+mod imports;
+mod merkle;
+mod permissions;
+mod persist;
+mod receipt;
+mod transaction;
+
+use backend::{Instance, Module};
+use cache::{CodeHash, ModuleCache};
+use env::{StatePtr, TransactionEnv};
+use merkle::MerkleTree;
+
+pub use backend::Backend;
+#[cfg(feature = "wasmer-backend")]
+pub use backend::Wasmer;
+#[cfg(feature = "wasmi-backend")]
+pub use backend::Wasmi;
+pub use builder::StateBuilder;
+pub(crate) use crypto::{blake2b, poseidon, verify_bls, verify_schnorr};
+pub use imports::{HostContext, HostFunctions, HOST_MODULE};
+pub use permissions::Permissions;
+pub use receipt::{Event, Receipt};
+pub use transaction::Transaction;
+
 //use rkyv::de::deserializers::*;
 use rkyv::validation::CheckArchiveError;
 use rkyv::{
@@ -14,10 +44,8 @@ use rkyv::{
 use rkyv::{AlignedVec, Deserialize, Infallible, Serialize};
 
 use thiserror::Error;
-use wasmer::{
-    imports, CompileError, ExportError, Function, ImportObject, Instance, LazyInit, Memory,
-    MemoryError, Module, RuntimeError, Store, WasmerEnv,
-};
+#[cfg(feature = "wasmer-backend")]
+use wasmer::{CompileError, ExportError, MemoryError, RuntimeError};
 
 type DefaultSerializer = CompositeSerializer<
     AlignedSerializer<AlignedVec>,
@@ -36,14 +64,76 @@ type DefaultSerializer = CompositeSerializer<
 pub enum VMError {
     #[error("Unknown contract")]
     UnknownContract,
+    #[error("Out of gas")]
+    OutOfGas,
+    #[error("Memory limit exceeded, {required} bytes required")]
+    MemoryLimitExceeded { required: usize },
+    #[error("Contract memory access out of bounds")]
+    OutOfBounds,
+    #[error("Reentrant call to contract {0:?}")]
+    Reentrancy(ContractId),
+    #[error("Apply called from within a query")]
+    ApplyInQuery,
+    #[error("State of contract {0:?} mutated in a query")]
+    StateMutatedInQuery(ContractId),
+    #[error("Contract {0:?} given as the caller of an apply")]
+    ContractCaller(ContractId),
+    #[error("Transfer made from within a query")]
+    TransferInQuery,
+    #[error("Insufficient balance, {required} required but {available} available")]
+    InsufficientBalance { required: u64, available: u64 },
+    #[error("Contract {contract:?} panicked in {method} at {location}: {message}")]
+    ContractPanic {
+        contract: ContractId,
+        method: String,
+        message: String,
+        location: String,
+    },
+    #[error("Contract {contract:?} trapped in {method}: {message}")]
+    ContractTrap {
+        contract: ContractId,
+        method: String,
+        message: String,
+    },
+    #[error("Contract {contract:?} has no method {method}")]
+    MethodNotFound {
+        contract: ContractId,
+        method: String,
+    },
+    #[error("Contract {contract:?} failed to instantiate: {reason}")]
+    InstantiationFailed {
+        contract: ContractId,
+        reason: String,
+    },
+    #[error("Import {module}.{name} is not allowed")]
+    ForbiddenImport { module: String, name: String },
+    #[error("Argument of {len} bytes exceeds the limit of {limit} bytes")]
+    ArgumentTooLarge { len: usize, limit: usize },
+    #[error("Invalid return value from {method} of contract {contract:?}: {reason}")]
+    InvalidReturnArchive {
+        contract: ContractId,
+        method: String,
+        reason: String,
+    },
+    #[error("Invalid archive: {0}")]
+    InvalidArchive(String),
+    #[error("Serialization failed: {0}")]
+    Serialization(String),
+    #[cfg(feature = "wasmer-backend")]
     #[error("{0}")]
     Exports(#[from] ExportError),
+    #[cfg(feature = "wasmer-backend")]
     #[error("{0}")]
     CompileError(#[from] CompileError),
+    #[cfg(feature = "wasmer-backend")]
     #[error("{0}")]
     RuntimeError(#[from] RuntimeError),
+    #[cfg(feature = "wasmer-backend")]
     #[error("{0}")]
     MemoryError(#[from] MemoryError),
+    #[cfg(feature = "wasmi-backend")]
+    #[error("{0}")]
+    Wasmi(#[from] wasmi::Error),
     #[error("{0}")]
     IO(#[from] io::Error),
     #[error("{0}")]
@@ -59,7 +149,7 @@ where
     C: Display,
 {
     fn from(comp: CompositeSerializerError<A, B, C>) -> Self {
-        VMError::Other(format!("{}", comp))
+        VMError::Serialization(format!("{}", comp))
     }
 }
 
@@ -69,60 +159,295 @@ where
     B: Display,
 {
     fn from(comp: CheckArchiveError<A, B>) -> Self {
-        VMError::Other(format!("{}", comp))
+        VMError::InvalidArchive(format!("{}", comp))
     }
 }
 
 #[derive(Debug)]
 struct ContractInstance {
     pub code: Vec<u8>,
+    pub code_hash: CodeHash,
     pub state: AlignedVec,
     pub state_ofs: i32,
+    pub permissions: Permissions,
 }
 
 impl ContractInstance {
-    fn id(&self) -> ContractId {
-        Default::default()
+    /// The id of a contract is the hash of its code, its initial state and the
+    /// nonce of the deployment, making it unique within a `State` and
+    /// reproducible across nodes deploying in the same order.
+    ///
+    /// The code and state are prefixed with their lengths, so that moving bytes
+    /// from one to the other changes the id.
+    fn id(&self, nonce: u64) -> ContractId {
+        let mut hasher = blake2b_simd::Params::new().hash_length(32).to_state();
+        hasher.update(&(self.code.len() as u64).to_le_bytes());
+        hasher.update(&self.code);
+        hasher.update(&(self.state.len() as u64).to_le_bytes());
+        hasher.update(&self.state);
+        hasher.update(&nonce.to_le_bytes());
+
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(hasher.finalize().as_bytes());
+        ContractId::from(bytes)
+    }
+
+    /// The hash of the code and state of the contract, making up its entry in
+    /// the root of the state.
+    ///
+    /// The offset of the state root is hashed along, since the same bytes
+    /// read from another root are another state.
+    fn leaf(&self) -> [u8; 32] {
+        let mut hasher = blake2b_simd::Params::new().hash_length(32).to_state();
+        hasher.update(&self.code_hash);
+        hasher.update(&self.state_ofs.to_le_bytes());
+        hasher.update(&self.state);
+
+        let mut leaf = [0u8; 32];
+        leaf.copy_from_slice(hasher.finalize().as_bytes());
+        leaf
     }
 }
 
+fn code_hash(code: &[u8]) -> CodeHash {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(
+        blake2b_simd::Params::new()
+            .hash_length(32)
+            .hash(code)
+            .as_bytes(),
+    );
+    hash
+}
+
 #[derive(Debug, Default)]
 pub struct State {
     map: Map<ContractId, ContractInstance>,
-    wasmer_store: Store,
+    nonce: u64,
+    backend: Box<dyn Backend>,
+    modules: ModuleCache,
+    // the contracts and balances the root is computed over
+    tree: MerkleTree,
+    // the native balances of accounts and contracts, without zero balances
+    balances: BTreeMap<Caller, u64>,
+    // the changes made to contracts, balances and the block, most recent last
+    journal: Vec<Change>,
+    in_transaction: bool,
+    // the block of the latest successful apply, also seen by queries
+    block: BlockContext,
+    // the caller of the executing apply, if any, and the value it attached
+    origin: Option<Caller>,
+    value: u64,
+    // the directory the state is persisted to, if any
+    path: Option<PathBuf>,
 }
 
-fn imports(store: &Store) -> ImportObject {
-    let env = TransactionEnv {
-        memory: LazyInit::new(),
-    };
+/// A change made in the course of an apply, recording what is needed to undo
+/// it.
+#[derive(Debug)]
+enum Change {
+    /// The state of a contract before it was modified.
+    State(ContractId, AlignedVec),
+    /// The balance of an account or contract before it was modified.
+    Balance(Caller, u64),
+    /// The block of the latest apply before this one.
+    Block(BlockContext),
+}
 
-    fn debug(env: &TransactionEnv, ofs: i32, len: i32) {
-        if let Some(mem) = env.memory.get_ref() {
-            let data = unsafe { mem.data_unchecked() };
-            let string = std::str::from_utf8(&data[ofs as usize..][..len as usize]).unwrap();
+/// Whether a call may modify the state of the contracts it touches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CallKind {
+    Query,
+    Apply,
+}
 
-            println!("CONTRACT DEBUG {:?}", string)
-        } else {
-            panic!("no memory no fun")
+/// A call to a contract method, with its argument already serialized.
+struct RawCall<'a> {
+    id: ContractId,
+    name: &'a str,
+    arg: &'a [u8],
+    arg_root: usize,
+    gas_limit: u64,
+    kind: CallKind,
+}
+
+impl<'a> RawCall<'a> {
+    fn new(
+        id: ContractId,
+        name: &'a str,
+        arg: &'a [u8],
+        arg_root: usize,
+        gas_limit: u64,
+        kind: CallKind,
+    ) -> Result<Self, VMError> {
+        if arg.len() > MAX_ARGUMENT_SIZE {
+            return Err(VMError::ArgumentTooLarge {
+                len: arg.len(),
+                limit: MAX_ARGUMENT_SIZE,
+            });
         }
+        if arg_root > arg.len() {
+            return Err(VMError::Other(format!(
+                "Argument root {} is outside the {} argument bytes",
+                arg_root,
+                arg.len()
+            )));
+        }
+
+        Ok(RawCall {
+            id,
+            name,
+            arg,
+            arg_root,
+            gas_limit,
+            kind,
+        })
     }
+}
 
-    imports! {
-            "env" => {
-                "debug" => Function::new_native_with_env(store, env, debug),
-            }
+/// Alignment of each region copied into contract memory, matching the
+/// alignment of an `AlignedVec`.
+const REGION_ALIGN: usize = 16;
+
+/// The largest memory a 32-bit wasm module can address.
+const MAX_MEMORY: usize = 1 << 32;
+
+fn align_up(ofs: usize) -> usize {
+    (ofs + REGION_ALIGN - 1) & !(REGION_ALIGN - 1)
+}
+
+/// Serializes a call argument, returning the bytes along with the position of
+/// the archived root within them.
+fn serialize_arg<M>(arg: &M) -> Result<(AlignedVec, usize), VMError>
+where
+    M: Serialize<DefaultSerializer>,
+{
+    let mut serialize = DefaultSerializer::default();
+    let root = serialize.serialize_value(arg)?;
+    Ok((serialize.into_serializer().into_inner(), root))
+}
+
+/// The error for a return value of `method` of `contract` failing validation.
+fn invalid_return(contract: ContractId, method: &str, error: impl Display) -> VMError {
+    VMError::InvalidReturnArchive {
+        contract,
+        method: method.into(),
+        reason: format!("{}", error),
     }
 }
 
-#[derive(WasmerEnv, Clone)]
-struct TransactionEnv {
-    #[wasmer(export)]
-    memory: LazyInit<Memory>,
+/// The layout of a call in contract memory.
+///
+/// The call is placed past the memory the contract starts out with, so as
+/// not to clobber its stack or statics. This is first the state of the
+/// contract, then the argument, and finally allocated space for the return
+/// value.
+#[derive(Debug)]
+struct CallLayout {
+    state_ofs: usize,
+    state_len: usize,
+    arg_ofs: usize,
+    arg_len: usize,
+    ret_ofs: usize,
+    ret_len: usize,
+}
+
+impl CallLayout {
+    fn new(base: usize, state_len: usize, arg_len: usize, ret_len: usize) -> Result<Self, VMError> {
+        let state_ofs = align_up(base);
+        let arg_ofs = align_up(state_ofs + state_len);
+        let ret_ofs = align_up(arg_ofs + arg_len);
+
+        let layout = CallLayout {
+            state_ofs,
+            state_len,
+            arg_ofs,
+            arg_len,
+            ret_ofs,
+            ret_len,
+        };
+
+        let required = layout.len();
+        if required > MAX_MEMORY {
+            return Err(VMError::MemoryLimitExceeded { required });
+        }
+
+        Ok(layout)
+    }
+
+    /// Lays out a call past the current memory of `instance`.
+    fn past_memory(
+        instance: &mut dyn Instance,
+        state_len: usize,
+        arg_len: usize,
+        ret_len: usize,
+    ) -> Result<Self, VMError> {
+        let base = instance.memory()?.len();
+        CallLayout::new(base, state_len, arg_len, ret_len)
+    }
+
+    /// The number of bytes of memory needed for the call.
+    fn len(&self) -> usize {
+        self.ret_ofs + self.ret_len
+    }
+
+    /// The state in memory.
+    fn state<'m>(&self, memory: &'m [u8]) -> &'m [u8] {
+        &memory[self.state_ofs..][..self.state_len]
+    }
+
+    /// Checks the length of the return value reported by `method` of
+    /// `contract`.
+    fn return_len(&self, len: i32, contract: ContractId, method: &str) -> Result<usize, VMError> {
+        let len = len as u32 as usize;
+        if len > self.ret_len {
+            let reason = format!("{} bytes exceed the return buffer", len);
+            return Err(invalid_return(contract, method, reason));
+        }
+        Ok(len)
+    }
+
+    /// Writes the state and argument into memory, growing it as needed.
+    fn write(&self, instance: &mut dyn Instance, state: &[u8], arg: &[u8]) -> Result<(), VMError> {
+        debug_assert_eq!(state.len(), self.state_len);
+        debug_assert_eq!(arg.len(), self.arg_len);
+
+        instance.grow_memory_to(self.len())?;
+        let mem_slice = instance.memory()?;
+
+        mem_slice[self.state_ofs..][..self.state_len].copy_from_slice(state);
+        mem_slice[self.arg_ofs..][..self.arg_len].copy_from_slice(arg);
+
+        Ok(())
+    }
 }
 
 impl State {
+    /// Configures a new `State`, e.g. with host functions of the embedder, a
+    /// backend or the capacity of its module cache.
+    pub fn builder() -> StateBuilder {
+        StateBuilder::default()
+    }
+
     pub fn deploy<State, Code>(&mut self, state: State, code: Code) -> Result<ContractId, VMError>
+    where
+        State: Debug + Serialize<DefaultSerializer>,
+        Code: Into<Vec<u8>>,
+    {
+        self.deploy_with_permissions(state, code, Permissions::all())
+    }
+
+    /// Deploys a contract that may only import what `permissions` allow,
+    /// failing if its code imports anything else.
+    ///
+    /// The permissions stay with the contract, and also apply to the code it
+    /// is upgraded to.
+    pub fn deploy_with_permissions<State, Code>(
+        &mut self,
+        state: State,
+        code: Code,
+        permissions: Permissions,
+    ) -> Result<ContractId, VMError>
     where
         State: Debug + Serialize<DefaultSerializer>,
         Code: Into<Vec<u8>>,
@@ -131,128 +456,549 @@ impl State {
         let state_ofs = serialize.serialize_value(&state)?;
         let state = serialize.into_serializer().into_inner();
 
+        self.deploy_raw(&state, state_ofs, code, permissions)
+    }
+
+    /// Deploys a contract with an already archived initial state, whose root
+    /// is at `state_root`, and the given import permissions.
+    pub fn deploy_raw<Code>(
+        &mut self,
+        state: &[u8],
+        state_root: usize,
+        code: Code,
+        permissions: Permissions,
+    ) -> Result<ContractId, VMError>
+    where
+        Code: Into<Vec<u8>>,
+    {
+        if state_root > state.len() {
+            return Err(VMError::Other(format!(
+                "State root {} is outside the {} state bytes",
+                state_root,
+                state.len()
+            )));
+        }
+
+        let mut aligned = AlignedVec::with_capacity(state.len());
+        aligned.extend_from_slice(state);
+        let state = aligned;
+
+        let code = code.into();
+        let code_hash = code_hash(&code);
+
+        // compile once up front, failing early on invalid code
+        let module = self
+            .modules
+            .get_or_compile(&code_hash, || self.backend.compile(&code))?;
+        permissions.check(&*module)?;
+
         let instance = ContractInstance {
-            code: code.into(),
+            code,
+            code_hash,
             state,
-            state_ofs: state_ofs as i32,
+            state_ofs: state_root as i32,
+            permissions,
         };
 
-        let id = instance.id();
+        let id = instance.id(self.nonce);
+        self.nonce += 1;
 
         self.map.insert(id, instance);
+        self.update_root(&[id]);
+
         Ok(id)
     }
 
-    pub fn query<M>(&self, id: ContractId, arg: &M) -> Result<M::Return, VMError>
+    pub fn query<M>(
+        &self,
+        id: ContractId,
+        arg: &M,
+        gas_limit: u64,
+    ) -> Result<Receipt<M::Return>, VMError>
     where
-        M: Method + Archive + for<'a> Serialize<WriteSerializer<&'a mut [u8]>>,
+        M: Method + Serialize<DefaultSerializer>,
         M::Return: Archive,
         <M::Return as Archive>::Archived: for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>
             + Deserialize<<M as Method>::Return, Infallible>,
     {
-        if let Some(contract) = self.map.get(&id) {
-            let module = Module::new(&self.wasmer_store, &contract.code)?;
-            let instance = Instance::new(&module, &imports(&self.wasmer_store)).unwrap();
-            let function = instance
-                .exports
-                .get_native_function::<(i32, i32, i32), ()>(M::NAME)?;
-            let memory = instance.exports.get_memory("memory")?;
+        let (arg, arg_root) = serialize_arg(arg)?;
+        let receipt = self.query_raw(id, M::NAME, &arg, arg_root, gas_limit)?;
 
-            // Copy the data the contract needs to execute correctly into its memory.
+        let archived = check_archived_root::<M::Return>(&receipt[..])
+            .map_err(|e| invalid_return(id, M::NAME, e))?;
+        let ret = archived.deserialize(&mut Infallible)?;
+        Ok(receipt.with_ret(ret))
+    }
+
+    /// Queries a contract by method name, with an already archived argument
+    /// whose root is at `arg_root`.
+    ///
+    /// Returns the archived return value as written by the contract, with its
+    /// root at the end, leaving its validation to the caller.
+    pub fn query_raw(
+        &self,
+        id: ContractId,
+        name: &str,
+        arg: &[u8],
+        arg_root: usize,
+        gas_limit: u64,
+    ) -> Result<Receipt<AlignedVec>, VMError> {
+        let call = RawCall::new(id, name, arg, arg_root, gas_limit, CallKind::Query)?;
+
+        // Queries never write through the pointer
+        let state = StatePtr(self as *const State as *mut State);
+        unsafe { State::call_raw(state, &[], call) }
+    }
+
+    /// Applies a transaction to a contract on behalf of `caller`, as part of
+    /// the given block.
+    ///
+    /// The attached `value` is transferred from the caller to the contract
+    /// before it executes, and back should the apply fail.
+    ///
+    /// The caller must be an account, since contracts only become callers by
+    /// calling one another.
+    ///
+    /// Queries see the block of the latest successful apply, until the next
+    /// one, but no caller unless they are made in the course of an apply.
+    pub fn apply<M>(
+        &mut self,
+        id: ContractId,
+        arg: &M,
+        caller: Caller,
+        value: u64,
+        block: BlockContext,
+        gas_limit: u64,
+    ) -> Result<Receipt<M::Return>, VMError>
+    where
+        M: Method + Serialize<DefaultSerializer>,
+        M::Return: Archive,
+        <M::Return as Archive>::Archived: for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>
+            + Deserialize<<M as Method>::Return, Infallible>,
+    {
+        let (arg, arg_root) = serialize_arg(arg)?;
+        let call = RawCall::new(id, M::NAME, &arg, arg_root, gas_limit, CallKind::Apply)?;
+
+        self.transact(call, caller, value, block, |receipt| {
+            let archived = check_archived_root::<<M as Method>::Return>(&receipt[..])
+                .map_err(|e| invalid_return(id, M::NAME, e))?;
+            let ret = archived.deserialize(&mut Infallible).expect("Infallible");
+            Ok(receipt.with_ret(ret))
+        })
+    }
 
-            // this is first the state of the contract, then the argument, and finally allocated space for the return value.
+    /// Applies a transaction to a contract by method name, with an already
+    /// archived argument whose root is at `arg_root`.
+    ///
+    /// Returns the archived return value as written by the contract, with its
+    /// root at the end, leaving its validation to the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_raw(
+        &mut self,
+        id: ContractId,
+        name: &str,
+        arg: &[u8],
+        arg_root: usize,
+        caller: Caller,
+        value: u64,
+        block: BlockContext,
+        gas_limit: u64,
+    ) -> Result<Receipt<AlignedVec>, VMError> {
+        let call = RawCall::new(id, name, arg, arg_root, gas_limit, CallKind::Apply)?;
+        self.transact(call, caller, value, block, Ok)
+    }
 
-            let (arg_ofs, ret_ofs) = unsafe {
-                // Unsafe because the compiler cannot guarantee that no one else is accessing this memory at this time
+    /// Executes an apply, keeping its changes only if both the call and the
+    /// processing of its return value succeed.
+    fn transact<R, F>(
+        &mut self,
+        call: RawCall,
+        caller: Caller,
+        value: u64,
+        block: BlockContext,
+        ret: F,
+    ) -> Result<Receipt<R>, VMError>
+    where
+        F: FnOnce(Receipt<AlignedVec>) -> Result<Receipt<R>, VMError>,
+    {
+        if let Caller::Contract(id) = caller {
+            return Err(VMError::ContractCaller(id));
+        }
 
-                let mem_slice = memory.data_unchecked_mut();
+        let mark = self.journal.len();
+        let previous = mem::replace(&mut self.block, block);
+        self.journal.push(Change::Block(previous));
+        self.origin = Some(caller);
+        self.value = value;
+
+        let result = self
+            .transfer(caller, Caller::Contract(call.id), value)
+            .and_then(|_| unsafe { State::call_raw(StatePtr(self), &[], call) })
+            .and_then(ret);
+
+        self.origin = None;
+        self.value = 0;
+
+        // A failed apply leaves no trace, including any changes made by the
+        // contracts it called.
+        match result {
+            Ok(_) => {
+                let touched: Vec<_> = self.journal[mark..]
+                    .iter()
+                    .filter_map(|change| match change {
+                        Change::State(id, _) => Some(*id),
+                        Change::Balance(..) | Change::Block(..) => None,
+                    })
+                    .collect();
+                self.update_root(&touched);
+
+                if !self.in_transaction {
+                    self.journal.clear();
+                }
+            }
+            Err(_) => self.revert(mark),
+        }
 
-                let state = &contract.state;
-                let state_len = state.len();
-                mem_slice[0..state_len].copy_from_slice(state);
+        result
+    }
 
-                let remaining_slice = &mut mem_slice[state_len..];
+    /// Replaces the code of a contract, keeping its id.
+    ///
+    /// If the new code exports [`MIGRATE_EXPORT`] it is called with the
+    /// current state, and the state it returns replaces it. Otherwise the
+    /// state is kept as is. Either the code and state are both replaced, or
+    /// the contract is left untouched.
+    ///
+    /// The new code is held to the permissions the contract was deployed
+    /// with.
+    pub fn upgrade<Code>(
+        &mut self,
+        id: ContractId,
+        code: Code,
+        gas_limit: u64,
+    ) -> Result<Receipt<()>, VMError>
+    where
+        Code: Into<Vec<u8>>,
+    {
+        let permissions = match self.map.get(&id) {
+            Some(contract) => &contract.permissions,
+            None => return Err(VMError::UnknownContract),
+        };
 
-                let state_len = state_len as i32;
+        let code = code.into();
+        let code_hash = code_hash(&code);
 
-                // Write the argument into wasm memory
+        let module = self
+            .modules
+            .get_or_compile(&code_hash, || self.backend.compile(&code))?;
+        permissions.check(&*module)?;
 
-                let mut serialize = WriteSerializer::new(remaining_slice);
-                let arg_ofs = state_len + serialize.serialize_value(arg)? as i32;
+        let migrated = if module.exports_function(MIGRATE_EXPORT) {
+            Some(unsafe { State::migrate_raw(StatePtr(self), id, &*module, gas_limit)? })
+        } else {
+            None
+        };
 
-                // TODO, make sure we have enough room in the memory for the return value
+        let contract = self.map.get_mut(&id).expect("contract checked above");
 
-                (arg_ofs, arg_ofs + mem::size_of::<M>() as i32)
-            };
+        contract.code = code;
+        contract.code_hash = code_hash;
 
-            function.call(contract.state_ofs, arg_ofs, ret_ofs)?;
+        let receipt = match migrated {
+            Some(receipt) => {
+                let (state, state_ofs) = receipt.ret;
+                contract.state = state;
+                contract.state_ofs = state_ofs;
 
-            unsafe {
-                let mem_slice = memory.data_unchecked();
-                let ret_ofs = ret_ofs as usize;
-                let ret_len = mem::size_of::<<M as Method>::Return>();
-                let ret_slice = &mem_slice[ret_ofs..][..ret_len];
-                let archived = check_archived_root::<M::Return>(ret_slice)?;
-                let a = archived.deserialize(&mut Infallible)?;
-                Ok(a)
+                Receipt::new((), receipt.gas_used, receipt.events)
             }
+            None => Receipt::new((), 0, Vec::new()),
+        };
+
+        self.update_root(&[id]);
+
+        Ok(receipt)
+    }
+
+    /// The ids of all deployed contracts, in no particular order.
+    pub fn contracts(&self) -> impl Iterator<Item = &ContractId> {
+        self.map.keys()
+    }
+
+    /// Begins a transaction, in which several applies can be executed and
+    /// later committed or rolled back together.
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// The native balance of an account or contract.
+    pub fn balance(&self, owner: &Caller) -> u64 {
+        self.balances.get(owner).copied().unwrap_or(0)
+    }
+
+    /// Credits an account or contract with newly issued native tokens.
+    pub fn deposit(&mut self, owner: Caller, amount: u64) -> Result<(), VMError> {
+        if let Caller::Contract(id) = owner {
+            if !self.map.contains_key(&id) {
+                return Err(VMError::UnknownContract);
+            }
+        }
+
+        let balance = self
+            .balance(&owner)
+            .checked_add(amount)
+            .ok_or_else(|| VMError::Other("Balance overflow".into()))?;
+        self.set_balance(owner, balance);
+
+        Ok(())
+    }
+
+    /// Moves native tokens between accounts or contracts, recording the
+    /// change in the journal.
+    fn transfer(&mut self, from: Caller, to: Caller, amount: u64) -> Result<(), VMError> {
+        if let Caller::Contract(id) = to {
+            if !self.map.contains_key(&id) {
+                return Err(VMError::UnknownContract);
+            }
+        }
+
+        let available = self.balance(&from);
+        if available < amount {
+            return Err(VMError::InsufficientBalance {
+                required: amount,
+                available,
+            });
+        }
+
+        if amount == 0 || from == to {
+            return Ok(());
+        }
+
+        let received = self
+            .balance(&to)
+            .checked_add(amount)
+            .ok_or_else(|| VMError::Other("Balance overflow".into()))?;
+
+        for (owner, balance) in [(from, available - amount), (to, received)] {
+            let previous = self.balance(&owner);
+            self.journal.push(Change::Balance(owner, previous));
+            self.set_balance(owner, balance);
+        }
+
+        Ok(())
+    }
+
+    /// Sets a balance, along with its entry in the root.
+    fn set_balance(&mut self, owner: Caller, balance: u64) {
+        let path = merkle::balance_path(&owner);
+
+        if balance == 0 {
+            self.balances.remove(&owner);
+            self.tree.remove(&path);
         } else {
-            Err(VMError::UnknownContract)
+            self.balances.insert(owner, balance);
+            self.tree.insert(path, &balance.to_le_bytes());
         }
     }
 
-    pub fn apply<M>(&mut self, id: ContractId, arg: &M) -> Result<M::Return, VMError>
-    where
-        M: Method + Archive + for<'a> Serialize<WriteSerializer<&'a mut [u8]>>,
-        M::Return: Archive,
-        <M::Return as Archive>::Archived: for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>
-            + Deserialize<<M as Method>::Return, Infallible>,
-    {
-        if let Some(contract) = self.map.get_mut(&id) {
-            let module = Module::new(&self.wasmer_store, &contract.code)?;
-            let instance = Instance::new(&module, &imports(&self.wasmer_store)).unwrap();
-            let function = instance
-                .exports
-                .get_native_function::<(i32, i32, i32), ()>(M::NAME)?;
-            let memory = instance.exports.get_memory("memory")?;
+    /// Undoes the changes recorded in the journal after `mark`.
+    fn revert(&mut self, mark: usize) {
+        let mut touched = Vec::new();
+
+        let changes: Vec<_> = self.journal.drain(mark..).rev().collect();
+        for change in changes {
+            match change {
+                Change::State(id, state) => {
+                    if let Some(contract) = self.map.get_mut(&id) {
+                        contract.state = state;
+                        touched.push(id);
+                    }
+                }
+                Change::Balance(owner, balance) => self.set_balance(owner, balance),
+                Change::Block(block) => self.block = block,
+            }
+        }
 
-            // Copy the data the contract needs to execute correctly into its memory.
+        self.update_root(&touched);
+    }
 
-            // this is first the state of the contract, then the argument, and finally allocated space for the return value.
+    /// The root hash of the state, covering the id, code and state of every
+    /// deployed contract, as well as all native balances.
+    ///
+    /// States that went through the same deployments and calls have the same
+    /// root, regardless of the machine they were executed on.
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
 
-            let state_len = contract.state.len();
+    /// Rehashes the entries of the given contracts, and the root with them.
+    fn update_root(&mut self, touched: &[ContractId]) {
+        for id in touched {
+            if let Some(contract) = self.map.get(id) {
+                self.tree
+                    .insert(merkle::contract_path(id), &contract.leaf());
+            }
+        }
+    }
 
-            let mem_slice = unsafe { memory.data_unchecked_mut() };
+    /// Executes a call, returning the archived return value.
+    ///
+    /// Contracts may call back into the state while executing, so it is only
+    /// accessed through a pointer, and never borrowed across the execution.
+    ///
+    /// # Safety
+    ///
+    /// `state` must be valid for the duration of the call, and may only be
+    /// written to if the call is an apply.
+    unsafe fn call_raw(
+        state: StatePtr,
+        callers: &[ContractId],
+        call: RawCall,
+    ) -> Result<Receipt<AlignedVec>, VMError> {
+        if callers.contains(&call.id) {
+            return Err(VMError::Reentrancy(call.id));
+        }
+
+        let mut callers = callers.to_vec();
+        callers.push(call.id);
 
-            // Write contract state into wasm memory
-            mem_slice[0..state_len].copy_from_slice(&contract.state);
+        let env = TransactionEnv::new(state, callers, call.name, call.kind);
+        let events = env.events();
 
-            let remaining_slice = &mut mem_slice[state_len..];
+        let (mut instance, layout, state_ofs) = {
+            let this = &*state.0;
+            let contract = this.map.get(&call.id).ok_or(VMError::UnknownContract)?;
 
-            // Write the argument into wasm memory
+            let module = this
+                .modules
+                .get_or_compile(&contract.code_hash, || this.backend.compile(&contract.code))?;
+            let mut instance = module.instantiate(env)?;
 
-            let mut serialize = WriteSerializer::new(remaining_slice);
-            let arg_ofs = state_len as i32 + serialize.serialize_value(arg)? as i32;
+            let layout = CallLayout::past_memory(
+                &mut *instance,
+                contract.state.len(),
+                call.arg.len(),
+                RETURN_BUFFER_SIZE,
+            )?;
 
-            // FIXME: make sure we have enough room in the memory
+            // Copy the data the contract needs to execute correctly into its memory.
+            layout.write(&mut *instance, &contract.state, call.arg)?;
 
-            let ret_ofs = arg_ofs + mem::size_of::<M>() as i32;
+            (instance, layout, contract.state_ofs)
+        };
 
-            function.call(contract.state_ofs, arg_ofs, ret_ofs)?;
+        let params = [
+            (layout.state_ofs + state_ofs as usize) as i32,
+            (layout.arg_ofs + call.arg_root) as i32,
+            layout.ret_ofs as i32,
+        ];
+        let (ret_len, gas_used) = instance.call(call.name, &params, call.gas_limit)?;
+        let ret_len = layout.return_len(ret_len, call.id, call.name)?;
+
+        let mem_slice = instance.memory()?;
+
+        match call.kind {
+            CallKind::Apply => {
+                let this = &mut *state.0;
+
+                // copy the possibly modified state back to the saved contract
+                // state, keeping the previous one around in case of a revert
+                if let Some(contract) = this.map.get_mut(&call.id) {
+                    let mut modified = AlignedVec::with_capacity(layout.state_len);
+                    modified.extend_from_slice(layout.state(mem_slice));
+
+                    let previous = mem::replace(&mut contract.state, modified);
+                    this.journal.push(Change::State(call.id, previous));
+                }
+            }
+            CallKind::Query => {
+                // the state is discarded after a query, but a query writing
+                // to it is a bug in the contract that should not go unnoticed
+                let this = &*state.0;
+
+                if let Some(contract) = this.map.get(&call.id) {
+                    if layout.state(mem_slice) != &contract.state[..] {
+                        return Err(VMError::StateMutatedInQuery(call.id));
+                    }
+                }
+            }
+        }
 
-            // copy the possibly modified state back to the saved contract state
+        let mut ret = AlignedVec::new();
+        ret.extend_from_slice(&mem_slice[layout.ret_ofs..][..ret_len]);
 
-            contract.state[..].copy_from_slice(&mem_slice[..state_len]);
+        let events = mem::take(&mut *events.lock().expect("poisoned event log"));
 
-            let ret_ofs = ret_ofs as usize;
-            let ret_len = mem::size_of::<<<M as Method>::Return as Archive>::Archived>();
+        Ok(Receipt::new(ret, gas_used, events))
+    }
 
-            let archived =
-                check_archived_root::<<M as Method>::Return>(&mem_slice[ret_ofs..][..ret_len])?;
+    /// Executes the migration export of `module` against the state of a
+    /// contract, returning the migrated state and the position of its root.
+    ///
+    /// # Safety
+    ///
+    /// `state` must be valid for the duration of the call, and is never
+    /// written to.
+    unsafe fn migrate_raw(
+        state: StatePtr,
+        id: ContractId,
+        module: &dyn Module,
+        gas_limit: u64,
+    ) -> Result<Receipt<(AlignedVec, i32)>, VMError> {
+        // The migration may only query other contracts
+        let env = TransactionEnv::new(state, vec![id], MIGRATE_EXPORT, CallKind::Query);
+        let events = env.events();
+
+        let mut instance = module.instantiate(env)?;
+
+        let (layout, state_ofs) = {
+            let this = &*state.0;
+            let contract = this.map.get(&id).ok_or(VMError::UnknownContract)?;
+
+            let layout = CallLayout::past_memory(
+                &mut *instance,
+                contract.state.len(),
+                0,
+                RETURN_BUFFER_SIZE,
+            )?;
+            layout.write(&mut *instance, &contract.state, &[])?;
+
+            (layout, contract.state_ofs)
+        };
 
-            Ok(archived.deserialize(&mut Infallible).expect("Infallible"))
-        } else {
-            Err(VMError::UnknownContract)
+        let params = [
+            (layout.state_ofs + state_ofs as usize) as i32,
+            layout.ret_ofs as i32,
+        ];
+        let (ret_len, gas_used) = instance.call(MIGRATE_EXPORT, &params, gas_limit)?;
+        let ret_len = layout.return_len(ret_len, id, MIGRATE_EXPORT)?;
+
+        let ret = &instance.memory()?[layout.ret_ofs..][..ret_len];
+
+        // the position of the root is appended to the archived state
+        let split = ret
+            .len()
+            .checked_sub(4)
+            .ok_or_else(|| invalid_return(id, MIGRATE_EXPORT, "missing state root"))?;
+        let (ret, root) = ret.split_at(split);
+
+        let mut root_bytes = [0u8; 4];
+        root_bytes.copy_from_slice(root);
+        let state_ofs = u32::from_le_bytes(root_bytes) as usize;
+
+        if state_ofs > ret.len() {
+            return Err(invalid_return(
+                id,
+                MIGRATE_EXPORT,
+                "state root out of bounds",
+            ));
         }
+
+        let mut migrated = AlignedVec::with_capacity(ret.len());
+        migrated.extend_from_slice(ret);
+
+        let events = mem::take(&mut *events.lock().expect("poisoned event log"));
+
+        Ok(Receipt::new((migrated, state_ofs as i32), gas_used, events))
     }
 }
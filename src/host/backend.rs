@@ -0,0 +1,88 @@
+//! The engines executing contract code.
+//!
+//! Contracts behave the same on every backend, so states executed on
+//! different ones can be cross-checked, save for the gas used by a call, which
+//! is metered by each backend in its own way. Cross-checking therefore only
+//! holds for calls given enough gas on every backend: a call whose gas limit
+//! lies between what it costs on two of them succeeds on one and runs out of
+//! gas on the other.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use super::env::TransactionEnv;
+use super::imports::HostFunctions;
+use super::VMError;
+
+#[cfg(feature = "wasmer-backend")]
+mod wasmer;
+#[cfg(feature = "wasmi-backend")]
+mod wasmi;
+
+#[cfg(feature = "wasmer-backend")]
+pub use self::wasmer::Wasmer;
+#[cfg(feature = "wasmi-backend")]
+pub use self::wasmi::Wasmi;
+
+#[cfg(not(any(feature = "wasmer-backend", feature = "wasmi-backend")))]
+compile_error!("the host needs a backend, enable `wasmer-backend` or `wasmi-backend`");
+
+/// Compiles contract code into modules ready to be instantiated.
+pub trait Backend: Debug + Send + Sync {
+    /// Compiles `code`, instrumenting it for gas metering.
+    fn compile(&self, code: &[u8]) -> Result<Arc<dyn Module>, VMError>;
+
+    /// Offers the host functions registered by the embedder to the modules
+    /// compiled from now on.
+    fn link(&mut self, functions: &HostFunctions) -> Result<(), VMError>;
+}
+
+/// Compiled contract code.
+pub trait Module: Debug + Send + Sync {
+    fn exports_function(&self, name: &str) -> bool;
+
+    /// The module and name of every import of the module.
+    fn imports(&self) -> Vec<(String, String)>;
+
+    /// Instantiates the module, with the host functions acting on `env`.
+    fn instantiate(&self, env: TransactionEnv) -> Result<Box<dyn Instance>, VMError>;
+}
+
+/// An instance of a contract, executing a single call.
+pub trait Instance {
+    /// The exported memory of the instance.
+    fn memory(&mut self) -> Result<&mut [u8], VMError>;
+
+    /// Grows the memory of the instance to at least `len` bytes.
+    fn grow_memory_to(&mut self, len: usize) -> Result<(), VMError>;
+
+    /// Calls an exported function, returning its result along with the gas
+    /// used.
+    fn call(&mut self, name: &str, params: &[i32], gas_limit: u64) -> Result<(i32, u64), VMError>;
+}
+
+/// The instance calling a host function.
+pub trait Caller {
+    /// The exported memory of the instance.
+    fn memory(&mut self) -> &mut [u8];
+
+    /// The gas left to the instance.
+    fn remaining_gas(&mut self) -> u64;
+
+    /// Charges the instance for gas spent on its behalf, e.g. by a nested call.
+    ///
+    /// The remaining gas is exhausted if `gas` exceeds it.
+    fn charge_gas(&mut self, gas: u64);
+}
+
+impl Default for Box<dyn Backend> {
+    #[cfg(feature = "wasmer-backend")]
+    fn default() -> Self {
+        Box::new(Wasmer::default())
+    }
+
+    #[cfg(not(feature = "wasmer-backend"))]
+    fn default() -> Self {
+        Box::new(Wasmi::default())
+    }
+}
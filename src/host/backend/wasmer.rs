@@ -0,0 +1,445 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use wasmer::wasmparser::Operator;
+use wasmer::{
+    imports, CompilerConfig, Cranelift, Exports, Function, FunctionType, Global, ImportObject,
+    LazyInit, Memory, Pages, RuntimeError, Store, Type, Universal, Val, WasmerEnv, WASM_PAGE_SIZE,
+};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_middlewares::Metering;
+
+use super::{Backend, Caller, Instance, Module};
+use crate::definitions::ContractId;
+use crate::host::env::TransactionEnv;
+use crate::host::{CallKind, HostFunctions, VMError};
+
+/// Compiles contracts to native code with wasmer and Cranelift.
+#[derive(Debug, Default)]
+pub struct Wasmer {
+    host_functions: HostFunctions,
+}
+
+impl Backend for Wasmer {
+    fn compile(&self, code: &[u8]) -> Result<Arc<dyn Module>, VMError> {
+        let module = wasmer::Module::new(&metered_store(), code)?;
+        Ok(Arc::new(WasmerModule {
+            module,
+            host_functions: self.host_functions.clone(),
+        }))
+    }
+
+    fn link(&mut self, functions: &HostFunctions) -> Result<(), VMError> {
+        self.host_functions = functions.clone();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct WasmerModule {
+    module: wasmer::Module,
+    // imports are put together for every instance
+    host_functions: HostFunctions,
+}
+
+impl Module for WasmerModule {
+    fn exports_function(&self, name: &str) -> bool {
+        self.module
+            .exports()
+            .functions()
+            .any(|export| export.name() == name)
+    }
+
+    fn imports(&self) -> Vec<(String, String)> {
+        self.module
+            .imports()
+            .map(|import| (import.module().to_string(), import.name().to_string()))
+            .collect()
+    }
+
+    fn instantiate(&self, env: TransactionEnv) -> Result<Box<dyn Instance>, VMError> {
+        let contract = env.contract();
+        let imports = imports(self.module.store(), Env::new(env), &self.host_functions);
+
+        let instance = wasmer::Instance::new(&self.module, &imports).map_err(|e| {
+            VMError::InstantiationFailed {
+                contract,
+                reason: e.to_string(),
+            }
+        })?;
+
+        Ok(Box::new(WasmerInstance { instance, contract }))
+    }
+}
+
+struct WasmerInstance {
+    instance: wasmer::Instance,
+    contract: ContractId,
+}
+
+impl WasmerInstance {
+    fn exported_memory(&self) -> Result<&Memory, VMError> {
+        Ok(self.instance.exports.get_memory("memory")?)
+    }
+
+    /// Recovers the error behind a trap in `method`, be it running out of
+    /// gas, an error raised by a host function or the contract trapping on
+    /// its own.
+    fn trap_error(&self, method: &str, error: RuntimeError) -> VMError {
+        if is_exhausted(&self.instance) {
+            return VMError::OutOfGas;
+        }
+        match error.downcast::<VMError>() {
+            Ok(error) => error,
+            Err(error) => VMError::ContractTrap {
+                contract: self.contract,
+                method: method.into(),
+                message: error.message(),
+            },
+        }
+    }
+}
+
+impl Instance for WasmerInstance {
+    fn memory(&mut self) -> Result<&mut [u8], VMError> {
+        // Unsafe because the compiler cannot guarantee that no one else is
+        // accessing this memory at this time
+        Ok(unsafe { self.exported_memory()?.data_unchecked_mut() })
+    }
+
+    fn grow_memory_to(&mut self, len: usize) -> Result<(), VMError> {
+        let memory = self.exported_memory()?;
+        let available = memory.data_size() as usize;
+
+        if len > available {
+            let missing = len - available;
+            let pages = missing.div_ceil(WASM_PAGE_SIZE);
+
+            memory
+                .grow(Pages(pages as u32))
+                .map_err(|_| VMError::MemoryLimitExceeded { required: len })?;
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, params: &[i32], gas_limit: u64) -> Result<(i32, u64), VMError> {
+        let function =
+            self.instance
+                .exports
+                .get_function(name)
+                .map_err(|_| VMError::MethodNotFound {
+                    contract: self.contract,
+                    method: name.into(),
+                })?;
+        let params: Vec<_> = params.iter().copied().map(Val::I32).collect();
+
+        set_remaining_points(&self.instance, gas_limit);
+
+        let results = function
+            .call(&params)
+            .map_err(|e| self.trap_error(name, e))?;
+        let gas_used = used(&self.instance, gas_limit);
+
+        match results.first().and_then(Val::i32) {
+            Some(ret) => Ok((ret, gas_used)),
+            None => Err(VMError::Other(format!("{} does not return an i32", name))),
+        }
+    }
+}
+
+/// The environment of the host functions, giving them access to the memory
+/// and gas of the instance.
+#[derive(WasmerEnv, Clone)]
+struct Env {
+    #[wasmer(export)]
+    memory: LazyInit<Memory>,
+    #[wasmer(export(name = "wasmer_metering_remaining_points"))]
+    remaining_points: LazyInit<Global>,
+    env: TransactionEnv,
+}
+
+impl Env {
+    fn new(env: TransactionEnv) -> Self {
+        Env {
+            memory: LazyInit::new(),
+            remaining_points: LazyInit::new(),
+            env,
+        }
+    }
+
+    fn remaining_points(&self) -> &Global {
+        self.remaining_points
+            .get_ref()
+            .expect("module is instrumented for gas metering")
+    }
+}
+
+struct HostCaller<'a>(&'a Env);
+
+impl Caller for HostCaller<'_> {
+    fn memory(&mut self) -> &mut [u8] {
+        let memory = self.0.memory.get_ref().expect("no memory no fun");
+        unsafe { memory.data_unchecked_mut() }
+    }
+
+    fn remaining_gas(&mut self) -> u64 {
+        remaining(self.0.remaining_points())
+    }
+
+    fn charge_gas(&mut self, gas: u64) {
+        let remaining_points = self.0.remaining_points();
+        let remaining = remaining(remaining_points).saturating_sub(gas);
+        remaining_points
+            .set(remaining.into())
+            .expect("remaining points are a mutable global");
+    }
+}
+
+fn imports(store: &Store, env: Env, functions: &HostFunctions) -> ImportObject {
+    fn debug(env: &Env, ofs: i32, len: i32) -> Result<(), VMError> {
+        env.env.debug(&mut HostCaller(env), ofs, len)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn panic(
+        env: &Env,
+        msg_ofs: i32,
+        msg_len: i32,
+        file_ofs: i32,
+        file_len: i32,
+        line: i32,
+        column: i32,
+    ) -> Result<(), VMError> {
+        env.env.panic(
+            &mut HostCaller(env),
+            msg_ofs,
+            msg_len,
+            file_ofs,
+            file_len,
+            line,
+            column,
+        )
+    }
+
+    fn emit(
+        env: &Env,
+        topic_ofs: i32,
+        topic_len: i32,
+        data_ofs: i32,
+        data_len: i32,
+    ) -> Result<(), VMError> {
+        env.env.emit(
+            &mut HostCaller(env),
+            topic_ofs,
+            topic_len,
+            data_ofs,
+            data_len,
+        )
+    }
+
+    fn caller(env: &Env, ofs: i32) -> Result<i32, VMError> {
+        env.env.caller_into(&mut HostCaller(env), ofs)
+    }
+
+    fn balance(env: &Env) -> i64 {
+        env.env.balance() as i64
+    }
+
+    fn value(env: &Env) -> i64 {
+        env.env.value() as i64
+    }
+
+    fn transfer(env: &Env, kind: i32, to_ofs: i32, amount: i64) -> Result<(), VMError> {
+        env.env.transfer(&mut HostCaller(env), kind, to_ofs, amount)
+    }
+
+    fn blake2b(env: &Env, data_ofs: i32, data_len: i32, hash_ofs: i32) -> Result<(), VMError> {
+        env.env
+            .blake2b(&mut HostCaller(env), data_ofs, data_len, hash_ofs)
+    }
+
+    fn poseidon(env: &Env, inputs_ofs: i32, inputs_len: i32, hash_ofs: i32) -> Result<(), VMError> {
+        env.env
+            .poseidon(&mut HostCaller(env), inputs_ofs, inputs_len, hash_ofs)
+    }
+
+    fn verify_schnorr(
+        env: &Env,
+        public_key_ofs: i32,
+        message_ofs: i32,
+        signature_ofs: i32,
+    ) -> Result<i32, VMError> {
+        env.env.verify_schnorr(
+            &mut HostCaller(env),
+            public_key_ofs,
+            message_ofs,
+            signature_ofs,
+        )
+    }
+
+    fn verify_bls(
+        env: &Env,
+        public_key_ofs: i32,
+        message_ofs: i32,
+        message_len: i32,
+        signature_ofs: i32,
+    ) -> Result<i32, VMError> {
+        env.env.verify_bls(
+            &mut HostCaller(env),
+            public_key_ofs,
+            message_ofs,
+            message_len,
+            signature_ofs,
+        )
+    }
+
+    fn block_height(env: &Env) -> i64 {
+        env.env.block().height as i64
+    }
+
+    fn block_timestamp(env: &Env) -> i64 {
+        env.env.block().timestamp as i64
+    }
+
+    fn chain_id(env: &Env) -> i64 {
+        env.env.block().chain_id as i64
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn query(
+        env: &Env,
+        id_ofs: i32,
+        name_ofs: i32,
+        name_len: i32,
+        arg_ofs: i32,
+        arg_len: i32,
+        arg_root: i32,
+        ret_ofs: i32,
+    ) -> Result<i32, VMError> {
+        env.env.call(
+            &mut HostCaller(env),
+            CallKind::Query,
+            id_ofs,
+            name_ofs,
+            name_len,
+            arg_ofs,
+            arg_len,
+            arg_root,
+            ret_ofs,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply(
+        env: &Env,
+        id_ofs: i32,
+        name_ofs: i32,
+        name_len: i32,
+        arg_ofs: i32,
+        arg_len: i32,
+        arg_root: i32,
+        ret_ofs: i32,
+    ) -> Result<i32, VMError> {
+        env.env.call(
+            &mut HostCaller(env),
+            CallKind::Apply,
+            id_ofs,
+            name_ofs,
+            name_len,
+            arg_ofs,
+            arg_len,
+            arg_root,
+            ret_ofs,
+        )
+    }
+
+    let mut import_object = imports! {
+            "env" => {
+                "debug" => Function::new_native_with_env(store, env.clone(), debug),
+                "panic" => Function::new_native_with_env(store, env.clone(), panic),
+                "emit" => Function::new_native_with_env(store, env.clone(), emit),
+                "caller" => Function::new_native_with_env(store, env.clone(), caller),
+                "balance" => Function::new_native_with_env(store, env.clone(), balance),
+                "value" => Function::new_native_with_env(store, env.clone(), value),
+                "transfer" => Function::new_native_with_env(store, env.clone(), transfer),
+                "blake2b" => Function::new_native_with_env(store, env.clone(), blake2b),
+                "poseidon" => Function::new_native_with_env(store, env.clone(), poseidon),
+                "verify_schnorr" => Function::new_native_with_env(store, env.clone(), verify_schnorr),
+                "verify_bls" => Function::new_native_with_env(store, env.clone(), verify_bls),
+                "block_height" => Function::new_native_with_env(store, env.clone(), block_height),
+                "block_timestamp" => Function::new_native_with_env(store, env.clone(), block_timestamp),
+                "chain_id" => Function::new_native_with_env(store, env.clone(), chain_id),
+                "query" => Function::new_native_with_env(store, env.clone(), query),
+                "apply" => Function::new_native_with_env(store, env.clone(), apply),
+            }
+    };
+
+    let mut modules: BTreeMap<&str, Exports> = BTreeMap::new();
+
+    for (module, name, function) in functions.iter() {
+        let signature = FunctionType::new(vec![Type::I32; function.params], vec![Type::I32]);
+        let function = function.clone();
+
+        let function = Function::new_with_env(
+            store,
+            signature,
+            env.clone(),
+            move |env: &Env, args: &[Val]| {
+                let args: Vec<_> = args.iter().map(Val::unwrap_i32).collect();
+                function
+                    .call(&mut HostCaller(env), env.env.contract(), &args)
+                    .map(|ret| vec![Val::I32(ret)])
+                    .map_err(|e| RuntimeError::user(Box::new(e)))
+            },
+        );
+        modules.entry(module).or_default().insert(name, function);
+    }
+
+    for (module, exports) in modules {
+        import_object.register(module, exports);
+    }
+
+    import_object
+}
+
+/// Every operator costs the same, keeping gas usage deterministic across
+/// nodes and compilers.
+fn cost(_operator: &Operator) -> u64 {
+    1
+}
+
+/// Creates a store instrumenting modules compiled with it for gas metering.
+///
+/// The metering middleware can only instrument a single module, so a new store
+/// is needed for every compilation.
+fn metered_store() -> Store {
+    // The initial limit is irrelevant, since it is set on every call.
+    let metering = Arc::new(Metering::new(0, cost));
+
+    let mut compiler = Cranelift::default();
+    compiler.push_middleware(metering);
+
+    Store::new(&Universal::new(compiler).engine())
+}
+
+/// The gas spent by the instance since the limit was set.
+fn used(instance: &wasmer::Instance, limit: u64) -> u64 {
+    match get_remaining_points(instance) {
+        MeteringPoints::Remaining(remaining) => limit - remaining,
+        MeteringPoints::Exhausted => limit,
+    }
+}
+
+fn is_exhausted(instance: &wasmer::Instance) -> bool {
+    matches!(get_remaining_points(instance), MeteringPoints::Exhausted)
+}
+
+/// The gas left to an instance, as seen from a host function it called.
+fn remaining(remaining_points: &Global) -> u64 {
+    remaining_points
+        .get()
+        .try_into()
+        .expect("remaining points are an i64 global")
+}
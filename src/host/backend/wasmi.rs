@@ -0,0 +1,491 @@
+use std::sync::Arc;
+
+use wasmi::core::{HostError, Pages, Trap, TrapCode, ValueType};
+use wasmi::{Config, Engine, Extern, FuncType, Linker, Store, Value};
+
+use super::{Backend, Caller, Instance, Module};
+use crate::definitions::ContractId;
+use crate::host::env::TransactionEnv;
+use crate::host::{CallKind, HostFunctions, VMError};
+
+const WASM_PAGE_SIZE: usize = 0x10000;
+
+/// Interprets contracts with wasmi, for targets where compiling to native code
+/// is not an option.
+///
+/// Gas is metered with wasmi's fuel, which charges more for some instructions
+/// than others. Its costs cannot be configured, so calls are charged other
+/// amounts than with wasmer, which charges one per operator.
+#[derive(Debug)]
+pub struct Wasmi {
+    engine: Engine,
+    // Linking host functions takes a lock on the engine that is also held
+    // while executing, so it has to be done once, up front, for nested calls
+    // not to deadlock.
+    linker: Arc<Linker<Env>>,
+}
+
+impl Default for Wasmi {
+    fn default() -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+
+        let engine = Engine::new(&config);
+        let linker =
+            linker(&engine, &HostFunctions::default()).expect("host functions are defined once");
+
+        Wasmi {
+            engine,
+            linker: Arc::new(linker),
+        }
+    }
+}
+
+impl Backend for Wasmi {
+    fn compile(&self, code: &[u8]) -> Result<Arc<dyn Module>, VMError> {
+        let module = wasmi::Module::new(&self.engine, code)?;
+        Ok(Arc::new(WasmiModule {
+            module,
+            linker: self.linker.clone(),
+        }))
+    }
+
+    fn link(&mut self, functions: &HostFunctions) -> Result<(), VMError> {
+        self.linker = Arc::new(linker(&self.engine, functions)?);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct WasmiModule {
+    module: wasmi::Module,
+    linker: Arc<Linker<Env>>,
+}
+
+impl Module for WasmiModule {
+    fn exports_function(&self, name: &str) -> bool {
+        self.module
+            .exports()
+            .any(|export| export.name() == name && export.ty().func().is_some())
+    }
+
+    fn imports(&self) -> Vec<(String, String)> {
+        self.module
+            .imports()
+            .map(|import| (import.module().to_string(), import.name().to_string()))
+            .collect()
+    }
+
+    fn instantiate(&self, env: TransactionEnv) -> Result<Box<dyn Instance>, VMError> {
+        let engine = self.module.engine();
+        let contract = env.contract();
+
+        let env = Env {
+            env: Arc::new(env),
+            fuel: 0,
+        };
+        let mut store = Store::new(engine, env);
+
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| VMError::InstantiationFailed {
+                contract,
+                reason: e.to_string(),
+            })?;
+
+        Ok(Box::new(WasmiInstance {
+            store,
+            instance,
+            contract,
+        }))
+    }
+}
+
+/// The data of the store an instance lives in.
+struct Env {
+    env: Arc<TransactionEnv>,
+    // the total fuel given to the instance
+    fuel: u64,
+}
+
+struct WasmiInstance {
+    store: Store<Env>,
+    instance: wasmi::Instance,
+    contract: ContractId,
+}
+
+impl WasmiInstance {
+    fn exported_memory(&self) -> Result<wasmi::Memory, VMError> {
+        self.instance
+            .get_memory(&self.store, "memory")
+            .ok_or_else(|| VMError::Other("Missing export memory".into()))
+    }
+
+    /// Gives the instance exactly `gas_limit` fuel to spend.
+    fn set_fuel(&mut self, gas_limit: u64) -> Result<(), VMError> {
+        let remaining = remaining_fuel(self.store.data(), self.store.fuel_consumed());
+
+        if gas_limit > remaining {
+            self.store
+                .add_fuel(gas_limit - remaining)
+                .map_err(wasmi::Error::from)?;
+            self.store.data_mut().fuel += gas_limit - remaining;
+        } else {
+            self.store
+                .consume_fuel(remaining - gas_limit)
+                .map_err(wasmi::Error::from)?;
+        }
+
+        Ok(())
+    }
+
+    /// Recovers the error behind a trap in `method`, be it running out of
+    /// gas, an error raised by a host function or the contract trapping on
+    /// its own.
+    fn trap_error(&self, method: &str, error: wasmi::Error) -> VMError {
+        match error {
+            wasmi::Error::Trap(trap) => {
+                if matches!(trap.trap_code(), Some(TrapCode::OutOfFuel)) {
+                    return VMError::OutOfGas;
+                }
+                if trap.downcast_ref::<VMError>().is_some() {
+                    return trap.downcast().expect("checked above");
+                }
+                VMError::ContractTrap {
+                    contract: self.contract,
+                    method: method.into(),
+                    message: trap.to_string(),
+                }
+            }
+            error => error.into(),
+        }
+    }
+}
+
+impl Instance for WasmiInstance {
+    fn memory(&mut self) -> Result<&mut [u8], VMError> {
+        let memory = self.exported_memory()?;
+        Ok(memory.data_mut(&mut self.store))
+    }
+
+    fn grow_memory_to(&mut self, len: usize) -> Result<(), VMError> {
+        let memory = self.exported_memory()?;
+        let available = memory.data(&self.store).len();
+
+        if len > available {
+            let missing = len - available;
+            let pages = missing.div_ceil(WASM_PAGE_SIZE);
+
+            Pages::new(pages as u32)
+                .and_then(|pages| memory.grow(&mut self.store, pages).ok())
+                .ok_or(VMError::MemoryLimitExceeded { required: len })?;
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, params: &[i32], gas_limit: u64) -> Result<(i32, u64), VMError> {
+        let function =
+            self.instance
+                .get_func(&self.store, name)
+                .ok_or_else(|| VMError::MethodNotFound {
+                    contract: self.contract,
+                    method: name.into(),
+                })?;
+        let params: Vec<_> = params.iter().copied().map(Value::I32).collect();
+        let mut results = [Value::I32(0)];
+
+        self.set_fuel(gas_limit)?;
+        let consumed = self
+            .store
+            .fuel_consumed()
+            .expect("fuel metering is enabled");
+
+        function
+            .call(&mut self.store, &params, &mut results)
+            .map_err(|e| self.trap_error(name, e))?;
+
+        let gas_used = self
+            .store
+            .fuel_consumed()
+            .expect("fuel metering is enabled")
+            - consumed;
+
+        match results[0] {
+            Value::I32(ret) => Ok((ret, gas_used)),
+            _ => Err(VMError::Other(format!("{} does not return an i32", name))),
+        }
+    }
+}
+
+fn remaining_fuel(env: &Env, consumed: Option<u64>) -> u64 {
+    env.fuel - consumed.expect("fuel metering is enabled")
+}
+
+impl HostError for VMError {}
+
+struct HostCaller<'a, 'b>(&'a mut wasmi::Caller<'b, Env>);
+
+impl HostCaller<'_, '_> {
+    fn env(&self) -> Arc<TransactionEnv> {
+        self.0.data().env.clone()
+    }
+}
+
+impl Caller for HostCaller<'_, '_> {
+    fn memory(&mut self) -> &mut [u8] {
+        let memory = self
+            .0
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .expect("no memory no fun");
+        memory.data_mut(&mut *self.0)
+    }
+
+    fn remaining_gas(&mut self) -> u64 {
+        remaining_fuel(self.0.data(), self.0.fuel_consumed())
+    }
+
+    fn charge_gas(&mut self, gas: u64) {
+        let gas = gas.min(self.remaining_gas());
+        self.0
+            .consume_fuel(gas)
+            .expect("charging at most the remaining fuel");
+    }
+}
+
+fn linker(engine: &Engine, functions: &HostFunctions) -> Result<Linker<Env>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+
+    for (module, name, function) in functions.iter() {
+        let signature = FuncType::new(vec![ValueType::I32; function.params], [ValueType::I32]);
+        let function = function.clone();
+
+        linker.func_new(
+            module,
+            name,
+            signature,
+            move |mut caller: wasmi::Caller<'_, Env>, args: &[Value], ret: &mut [Value]| {
+                let args: Vec<_> = args.iter().filter_map(Value::i32).collect();
+                let mut caller = HostCaller(&mut caller);
+                let contract = caller.env().contract();
+
+                ret[0] = Value::I32(function.call(&mut caller, contract, &args)?);
+                Ok(())
+            },
+        )?;
+    }
+
+    linker.func_wrap(
+        "env",
+        "debug",
+        |mut caller: wasmi::Caller<'_, Env>, ofs: i32, len: i32| -> Result<(), Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .debug(&mut caller, ofs, len)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "panic",
+        |mut caller: wasmi::Caller<'_, Env>,
+         msg_ofs: i32,
+         msg_len: i32,
+         file_ofs: i32,
+         file_len: i32,
+         line: i32,
+         column: i32|
+         -> Result<(), Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .panic(
+                    &mut caller,
+                    msg_ofs,
+                    msg_len,
+                    file_ofs,
+                    file_len,
+                    line,
+                    column,
+                )
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "emit",
+        |mut caller: wasmi::Caller<'_, Env>,
+         topic_ofs: i32,
+         topic_len: i32,
+         data_ofs: i32,
+         data_len: i32|
+         -> Result<(), Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .emit(&mut caller, topic_ofs, topic_len, data_ofs, data_len)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "caller",
+        |mut caller: wasmi::Caller<'_, Env>, ofs: i32| -> Result<i32, Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .caller_into(&mut caller, ofs)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap("env", "balance", |caller: wasmi::Caller<'_, Env>| {
+        caller.data().env.balance() as i64
+    })?;
+
+    linker.func_wrap("env", "value", |caller: wasmi::Caller<'_, Env>| {
+        caller.data().env.value() as i64
+    })?;
+
+    linker.func_wrap(
+        "env",
+        "transfer",
+        |mut caller: wasmi::Caller<'_, Env>,
+         kind: i32,
+         to_ofs: i32,
+         amount: i64|
+         -> Result<(), Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .transfer(&mut caller, kind, to_ofs, amount)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "blake2b",
+        |mut caller: wasmi::Caller<'_, Env>,
+         data_ofs: i32,
+         data_len: i32,
+         hash_ofs: i32|
+         -> Result<(), Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .blake2b(&mut caller, data_ofs, data_len, hash_ofs)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "poseidon",
+        |mut caller: wasmi::Caller<'_, Env>,
+         inputs_ofs: i32,
+         inputs_len: i32,
+         hash_ofs: i32|
+         -> Result<(), Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .poseidon(&mut caller, inputs_ofs, inputs_len, hash_ofs)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "verify_schnorr",
+        |mut caller: wasmi::Caller<'_, Env>,
+         public_key_ofs: i32,
+         message_ofs: i32,
+         signature_ofs: i32|
+         -> Result<i32, Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .verify_schnorr(&mut caller, public_key_ofs, message_ofs, signature_ofs)
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "verify_bls",
+        |mut caller: wasmi::Caller<'_, Env>,
+         public_key_ofs: i32,
+         message_ofs: i32,
+         message_len: i32,
+         signature_ofs: i32|
+         -> Result<i32, Trap> {
+            let mut caller = HostCaller(&mut caller);
+            caller
+                .env()
+                .verify_bls(
+                    &mut caller,
+                    public_key_ofs,
+                    message_ofs,
+                    message_len,
+                    signature_ofs,
+                )
+                .map_err(Trap::from)
+        },
+    )?;
+
+    linker.func_wrap("env", "block_height", |caller: wasmi::Caller<'_, Env>| {
+        caller.data().env.block().height as i64
+    })?;
+
+    linker.func_wrap(
+        "env",
+        "block_timestamp",
+        |caller: wasmi::Caller<'_, Env>| caller.data().env.block().timestamp as i64,
+    )?;
+
+    linker.func_wrap("env", "chain_id", |caller: wasmi::Caller<'_, Env>| {
+        caller.data().env.block().chain_id as i64
+    })?;
+
+    for (name, kind) in [("query", CallKind::Query), ("apply", CallKind::Apply)] {
+        linker.func_wrap(
+            "env",
+            name,
+            move |mut caller: wasmi::Caller<'_, Env>,
+                  id_ofs: i32,
+                  name_ofs: i32,
+                  name_len: i32,
+                  arg_ofs: i32,
+                  arg_len: i32,
+                  arg_root: i32,
+                  ret_ofs: i32|
+                  -> Result<i32, Trap> {
+                let mut caller = HostCaller(&mut caller);
+                caller
+                    .env()
+                    .call(
+                        &mut caller,
+                        kind,
+                        id_ofs,
+                        name_ofs,
+                        name_len,
+                        arg_ofs,
+                        arg_len,
+                        arg_root,
+                        ret_ofs,
+                    )
+                    .map_err(Trap::from)
+            },
+        )?;
+    }
+
+    Ok(linker)
+}
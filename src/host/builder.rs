@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use super::cache::ModuleCache;
+use super::{Backend, HostContext, HostFunctions, State, VMError};
+
+/// Configures a new `State`.
+///
+/// ```ignore
+/// let state = State::builder()
+///     .host_function("chain", "epoch", 0, |_, _| Ok(EPOCH))
+///     .build()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct StateBuilder {
+    backend: Option<Box<dyn Backend>>,
+    module_cache_capacity: Option<usize>,
+    host_functions: HostFunctions,
+}
+
+impl StateBuilder {
+    /// Executes contracts on the given backend.
+    pub fn backend<B: Backend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Keeps at most `capacity` compiled modules, which must be at least one.
+    pub fn module_cache_capacity(mut self, capacity: usize) -> Self {
+        self.module_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Offers `function` to contracts as the import `module.name`, taking
+    /// `params` parameters of type `i32` and returning an `i32`.
+    ///
+    /// The function is handed the calling contract, with access to its memory
+    /// and gas, and any error it returns aborts the call. Registering a
+    /// function under the same name again replaces it.
+    pub fn host_function<F>(mut self, module: &str, name: &str, params: usize, function: F) -> Self
+    where
+        F: Fn(&mut HostContext, &[i32]) -> Result<i32, VMError> + Send + Sync + 'static,
+    {
+        self.host_functions.insert(module, name, params, function);
+        self
+    }
+
+    /// Creates the state.
+    ///
+    /// Fails if a host function is registered in the module of the built-in
+    /// ones, [`HOST_MODULE`](super::HOST_MODULE), or if the module cache is
+    /// given no capacity.
+    pub fn build(self) -> Result<State, VMError> {
+        self.host_functions.validate()?;
+
+        let mut backend = self.backend.unwrap_or_default();
+        backend.link(&self.host_functions)?;
+
+        let modules = match self.module_cache_capacity {
+            Some(0) => {
+                return Err(VMError::Other(
+                    "Module cache capacity must be non-zero".into(),
+                ))
+            }
+            Some(capacity) => ModuleCache::new(capacity),
+            None => ModuleCache::default(),
+        };
+
+        Ok(State {
+            backend,
+            modules,
+            ..Default::default()
+        })
+    }
+
+    /// Creates the state and loads the one persisted in the directory at
+    /// `path` into it, as [`State::open`] does.
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<State, VMError> {
+        self.build()?.load(path.as_ref())
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap as Map;
+use std::sync::{Arc, Mutex};
+
+use super::backend::Module;
+use super::VMError;
+
+pub type CodeHash = [u8; 32];
+
+/// The number of compiled modules kept around by default.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// A bounded cache of compiled modules, keyed by the hash of their code.
+///
+/// When the cache is full, the least recently used module is evicted.
+#[derive(Debug)]
+pub struct ModuleCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    modules: Map<CodeHash, (Arc<dyn Module>, u64)>,
+    tick: u64,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        ModuleCache::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ModuleCache {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "module cache capacity must be non-zero");
+        ModuleCache {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Returns the cached module for `hash`, calling `compile` on a miss.
+    pub fn get_or_compile<F>(&self, hash: &CodeHash, compile: F) -> Result<Arc<dyn Module>, VMError>
+    where
+        F: FnOnce() -> Result<Arc<dyn Module>, VMError>,
+    {
+        let mut inner = self.inner.lock().expect("poisoned module cache");
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if let Some((module, last_used)) = inner.modules.get_mut(hash) {
+            *last_used = tick;
+            return Ok(module.clone());
+        }
+
+        let module = compile()?;
+
+        if inner.modules.len() >= self.capacity {
+            let lru = inner
+                .modules
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(hash, _)| *hash);
+
+            if let Some(lru) = lru {
+                inner.modules.remove(&lru);
+            }
+        }
+
+        inner.modules.insert(*hash, (module.clone(), tick));
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::host::Backend;
+
+    // the smallest valid wasm module
+    const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn evicts_least_recently_used() -> Result<(), VMError> {
+        let backend = Box::<dyn Backend>::default();
+        let cache = ModuleCache::new(2);
+        let compile = || backend.compile(EMPTY_MODULE);
+
+        cache.get_or_compile(&[0; 32], compile)?;
+        cache.get_or_compile(&[1; 32], compile)?;
+
+        // touch the first module so that the second one is evicted
+        cache.get_or_compile(&[0; 32], compile)?;
+        cache.get_or_compile(&[2; 32], compile)?;
+
+        let inner = cache.inner.lock().unwrap();
+
+        assert_eq!(inner.modules.len(), 2);
+        assert!(inner.modules.contains_key(&[0; 32]));
+        assert!(!inner.modules.contains_key(&[1; 32]));
+        assert!(inner.modules.contains_key(&[2; 32]));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Cryptographic primitives offered to contracts as host functions.
+//!
+//! The same implementations back the native mockups in `abi`, so contracts
+//! behave identically whether tested natively or deployed.
+
+use dusk_bls12_381::BlsScalar;
+use dusk_bytes::Serializable;
+
+use super::VMError;
+use crate::definitions::{
+    BLS_PUBLIC_KEY_SIZE, BLS_SIGNATURE_SIZE, SCALAR_SIZE, SCHNORR_PUBLIC_KEY_SIZE,
+    SCHNORR_SIGNATURE_SIZE,
+};
+
+/// The 32 byte Blake2b hash of `bytes`.
+pub fn blake2b(bytes: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(
+        blake2b_simd::Params::new()
+            .hash_length(32)
+            .hash(bytes)
+            .as_bytes(),
+    );
+    hash
+}
+
+/// The Poseidon sponge hash of a sequence of scalars.
+///
+/// Fails if any of the inputs is not a canonical encoding of a scalar.
+pub fn poseidon(inputs: &[[u8; SCALAR_SIZE]]) -> Result<[u8; SCALAR_SIZE], VMError> {
+    let scalars = inputs
+        .iter()
+        .map(|bytes| BlsScalar::from_bytes(bytes))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| VMError::Other("Invalid scalar".into()))?;
+
+    Ok(dusk_poseidon::sponge::hash(&scalars).to_bytes())
+}
+
+/// Verifies a Schnorr signature over a scalar message.
+///
+/// Malformed keys, messages or signatures never verify.
+pub fn verify_schnorr(
+    public_key: &[u8; SCHNORR_PUBLIC_KEY_SIZE],
+    message: &[u8; SCALAR_SIZE],
+    signature: &[u8; SCHNORR_SIGNATURE_SIZE],
+) -> bool {
+    let public_key = dusk_schnorr::PublicKey::from_bytes(public_key);
+    let message = BlsScalar::from_bytes(message);
+    let signature = dusk_schnorr::Signature::from_bytes(signature);
+
+    match (public_key, message, signature) {
+        (Ok(public_key), Ok(message), Ok(signature)) => signature.verify(&public_key, message),
+        _ => false,
+    }
+}
+
+/// Verifies a BLS signature over an arbitrary message.
+///
+/// Malformed keys or signatures never verify.
+pub fn verify_bls(
+    public_key: &[u8; BLS_PUBLIC_KEY_SIZE],
+    message: &[u8],
+    signature: &[u8; BLS_SIGNATURE_SIZE],
+) -> bool {
+    let public_key = dusk_bls12_381_sign::PublicKey::from_bytes(public_key);
+    let signature = dusk_bls12_381_sign::Signature::from_bytes(signature);
+
+    match (public_key, signature) {
+        (Ok(public_key), Ok(signature)) => public_key.verify(&signature, message).is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn blake2b_known_vectors() {
+        assert_eq!(
+            hex(&blake2b(b"")),
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        );
+        assert_eq!(
+            hex(&blake2b(b"abc")),
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319"
+        );
+    }
+
+    #[test]
+    fn poseidon_rejects_non_canonical_scalars() {
+        let one = BlsScalar::one().to_bytes();
+        assert!(poseidon(&[one, one]).is_ok());
+
+        // larger than the modulus of the scalar field
+        match poseidon(&[one, [0xff; SCALAR_SIZE]]) {
+            Err(VMError::Other(reason)) => assert_eq!(reason, "Invalid scalar"),
+            other => panic!("expected an invalid scalar, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn schnorr_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0xc0ffee);
+
+        let secret_key = dusk_schnorr::SecretKey::random(&mut rng);
+        let public_key = dusk_schnorr::PublicKey::from(&secret_key);
+        let message = BlsScalar::from(42u64);
+        let signature = dusk_schnorr::Signature::new(&secret_key, &mut rng, message);
+
+        let public_key = public_key.to_bytes();
+        let message = message.to_bytes();
+        let mut signature = signature.to_bytes();
+        assert!(verify_schnorr(&public_key, &message, &signature));
+
+        let other = BlsScalar::from(43u64).to_bytes();
+        assert!(!verify_schnorr(&public_key, &other, &signature));
+
+        signature[0] ^= 1;
+        assert!(!verify_schnorr(&public_key, &message, &signature));
+    }
+
+    #[test]
+    fn bls_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0xc0ffee);
+
+        let secret_key = dusk_bls12_381_sign::SecretKey::random(&mut rng);
+        let public_key = dusk_bls12_381_sign::PublicKey::from(&secret_key);
+        let message = b"the medium is the message";
+        let signature = secret_key.sign(&public_key, message);
+
+        let public_key = public_key.to_bytes();
+        let mut signature = signature.to_bytes();
+        assert!(verify_bls(&public_key, message, &signature));
+        assert!(!verify_bls(&public_key, b"another message", &signature));
+
+        signature[0] ^= 1;
+        assert!(!verify_bls(&public_key, message, &signature));
+    }
+}
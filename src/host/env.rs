@@ -0,0 +1,402 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use super::backend::Caller;
+use super::crypto;
+use super::{CallKind, Event, RawCall, State, VMError};
+use crate::definitions::{
+    self, BlockContext, ContractId, ACCOUNT_CALLER, CONTRACT_CALLER, NO_CALLER, SCALAR_SIZE,
+};
+use rkyv::AlignedVec;
+
+// The gas charged by the cryptographic host functions, on top of the
+// instructions executed by the contract, in line with the work they do.
+const BLAKE2B_GAS: u64 = 100;
+const BLAKE2B_GAS_PER_BYTE: u64 = 1;
+const POSEIDON_GAS_PER_INPUT: u64 = 5_000;
+const SCHNORR_GAS: u64 = 100_000;
+const BLS_GAS: u64 = 500_000;
+const BLS_GAS_PER_BYTE: u64 = 1;
+
+// The gas charged for emitting an event, whose topic and data are copied out
+// of contract memory into the receipt.
+const EMIT_GAS: u64 = 500;
+const EMIT_GAS_PER_BYTE: u64 = 1;
+
+/// A pointer to the `State` a call is executed against, through which host
+/// functions perform nested calls.
+#[derive(Clone, Copy)]
+pub struct StatePtr(pub *mut State);
+
+// The pointer is only dereferenced while the call it was created for is
+// executing, on the thread that issued it.
+unsafe impl Send for StatePtr {}
+unsafe impl Sync for StatePtr {}
+
+/// The environment host functions execute in, independently of the backend.
+#[derive(Clone)]
+pub struct TransactionEnv {
+    state: StatePtr,
+    // the call stack, ending with the executing contract
+    callers: Vec<ContractId>,
+    // the method called on the executing contract
+    method: String,
+    kind: CallKind,
+    // the events emitted by the contract and the ones it called
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl TransactionEnv {
+    pub(crate) fn new(
+        state: StatePtr,
+        callers: Vec<ContractId>,
+        method: &str,
+        kind: CallKind,
+    ) -> Self {
+        TransactionEnv {
+            state,
+            callers,
+            method: method.into(),
+            kind,
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub(crate) fn events(&self) -> Arc<Mutex<Vec<Event>>> {
+        self.events.clone()
+    }
+
+    /// The executing contract.
+    pub(crate) fn contract(&self) -> ContractId {
+        *self.callers.last().expect("a contract is executing")
+    }
+
+    /// Prints a message from the contract, with any invalid UTF-8 replaced.
+    pub(crate) fn debug(&self, caller: &mut dyn Caller, ofs: i32, len: i32) -> Result<(), VMError> {
+        let data = caller.memory();
+        let message = &data[region(data, ofs, len as u32 as usize)?];
+
+        println!("CONTRACT DEBUG {:?}", String::from_utf8_lossy(message));
+
+        Ok(())
+    }
+
+    pub(crate) fn emit(
+        &self,
+        caller: &mut dyn Caller,
+        topic_ofs: i32,
+        topic_len: i32,
+        data_ofs: i32,
+        data_len: i32,
+    ) -> Result<(), VMError> {
+        let topic_len = topic_len as u32 as usize;
+        let data_len = data_len as u32 as usize;
+        charge(
+            caller,
+            EMIT_GAS + EMIT_GAS_PER_BYTE * (topic_len as u64 + data_len as u64),
+        )?;
+
+        let data = caller.memory();
+
+        let topic = std::str::from_utf8(&data[region(data, topic_ofs, topic_len)?])
+            .map_err(|_| VMError::Other("Invalid event topic".into()))?
+            .to_string();
+        let event = data[region(data, data_ofs, data_len)?].to_vec();
+
+        let source = self.contract();
+
+        self.events
+            .lock()
+            .expect("poisoned event log")
+            .push(Event::new(source, topic, event));
+
+        Ok(())
+    }
+
+    /// The block the executing transaction is applied in.
+    pub(crate) fn block(&self) -> BlockContext {
+        unsafe { (*self.state.0).block }
+    }
+
+    /// The caller of the executing contract, if any.
+    fn caller(&self) -> Option<definitions::Caller> {
+        match self.callers.len() {
+            n if n >= 2 => Some(definitions::Caller::Contract(self.callers[n - 2])),
+            _ => unsafe { (*self.state.0).origin },
+        }
+    }
+
+    /// Writes the identity of the caller of the executing contract into its
+    /// memory, returning its kind.
+    pub(crate) fn caller_into(&self, caller: &mut dyn Caller, ofs: i32) -> Result<i32, VMError> {
+        let (kind, bytes) = match self.caller() {
+            Some(definitions::Caller::Account(account)) => (ACCOUNT_CALLER, account),
+            Some(definitions::Caller::Contract(id)) => (CONTRACT_CALLER, *id.as_bytes()),
+            None => return Ok(NO_CALLER),
+        };
+
+        let data = caller.memory();
+        let out = region(data, ofs, bytes.len())?;
+        data[out].copy_from_slice(&bytes);
+
+        Ok(kind)
+    }
+
+    /// The native balance of the executing contract.
+    pub(crate) fn balance(&self) -> u64 {
+        let id = self.contract();
+        unsafe { (*self.state.0).balance(&definitions::Caller::Contract(id)) }
+    }
+
+    /// The value attached to the call, only ever non-zero for the contract
+    /// applied to directly.
+    pub(crate) fn value(&self) -> u64 {
+        match self.callers.len() {
+            1 => unsafe { (*self.state.0).value },
+            _ => 0,
+        }
+    }
+
+    /// Transfers native tokens from the executing contract to an account or
+    /// contract.
+    pub(crate) fn transfer(
+        &self,
+        caller: &mut dyn Caller,
+        kind: i32,
+        to_ofs: i32,
+        amount: i64,
+    ) -> Result<(), VMError> {
+        if self.kind == CallKind::Query {
+            return Err(VMError::TransferInQuery);
+        }
+
+        let data = caller.memory();
+
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&data[region(data, to_ofs, 32)?]);
+
+        let to = match kind {
+            ACCOUNT_CALLER => definitions::Caller::Account(bytes),
+            CONTRACT_CALLER => definitions::Caller::Contract(ContractId::from(bytes)),
+            _ => {
+                return Err(VMError::Other(format!(
+                    "Invalid kind of recipient {}",
+                    kind
+                )))
+            }
+        };
+        let from = definitions::Caller::Contract(self.contract());
+
+        let state = unsafe { &mut *self.state.0 };
+        state.transfer(from, to, amount as u64)
+    }
+
+    /// Hashes data in contract memory with Blake2b, writing the hash back.
+    pub(crate) fn blake2b(
+        &self,
+        caller: &mut dyn Caller,
+        data_ofs: i32,
+        data_len: i32,
+        hash_ofs: i32,
+    ) -> Result<(), VMError> {
+        let len = data_len as u32 as usize;
+        charge(caller, BLAKE2B_GAS + BLAKE2B_GAS_PER_BYTE * len as u64)?;
+
+        let data = caller.memory();
+        let hash = crypto::blake2b(&data[region(data, data_ofs, len)?]);
+
+        let out = region(data, hash_ofs, hash.len())?;
+        data[out].copy_from_slice(&hash);
+
+        Ok(())
+    }
+
+    /// Hashes scalars in contract memory with Poseidon, writing the hash back.
+    pub(crate) fn poseidon(
+        &self,
+        caller: &mut dyn Caller,
+        inputs_ofs: i32,
+        inputs_len: i32,
+        hash_ofs: i32,
+    ) -> Result<(), VMError> {
+        let n = inputs_len as u32 as usize;
+        charge(caller, POSEIDON_GAS_PER_INPUT * n as u64)?;
+
+        let data = caller.memory();
+
+        let bytes = &data[region(data, inputs_ofs, n * SCALAR_SIZE)?];
+        let inputs: Vec<[u8; SCALAR_SIZE]> = bytes
+            .chunks_exact(SCALAR_SIZE)
+            .map(|chunk| {
+                let mut scalar = [0u8; SCALAR_SIZE];
+                scalar.copy_from_slice(chunk);
+                scalar
+            })
+            .collect();
+
+        let hash = crypto::poseidon(&inputs)?;
+
+        let out = region(data, hash_ofs, hash.len())?;
+        data[out].copy_from_slice(&hash);
+
+        Ok(())
+    }
+
+    /// Verifies a Schnorr signature in contract memory, returning 1 if it is
+    /// valid and 0 otherwise.
+    pub(crate) fn verify_schnorr(
+        &self,
+        caller: &mut dyn Caller,
+        public_key_ofs: i32,
+        message_ofs: i32,
+        signature_ofs: i32,
+    ) -> Result<i32, VMError> {
+        charge(caller, SCHNORR_GAS)?;
+
+        let data = caller.memory();
+
+        let public_key = array(data, public_key_ofs)?;
+        let message = array(data, message_ofs)?;
+        let signature = array(data, signature_ofs)?;
+
+        Ok(crypto::verify_schnorr(&public_key, &message, &signature) as i32)
+    }
+
+    /// Verifies a BLS signature in contract memory, returning 1 if it is valid
+    /// and 0 otherwise.
+    pub(crate) fn verify_bls(
+        &self,
+        caller: &mut dyn Caller,
+        public_key_ofs: i32,
+        message_ofs: i32,
+        message_len: i32,
+        signature_ofs: i32,
+    ) -> Result<i32, VMError> {
+        let len = message_len as u32 as usize;
+        charge(caller, BLS_GAS + BLS_GAS_PER_BYTE * len as u64)?;
+
+        let data = caller.memory();
+
+        let public_key = array(data, public_key_ofs)?;
+        let message = &data[region(data, message_ofs, len)?];
+        let signature = array(data, signature_ofs)?;
+
+        Ok(crypto::verify_bls(&public_key, message, &signature) as i32)
+    }
+
+    /// Reads the panic reported by the contract, always returning it as an
+    /// error to abort execution.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn panic(
+        &self,
+        caller: &mut dyn Caller,
+        msg_ofs: i32,
+        msg_len: i32,
+        file_ofs: i32,
+        file_len: i32,
+        line: i32,
+        column: i32,
+    ) -> Result<(), VMError> {
+        let data = caller.memory();
+
+        let message = &data[region(data, msg_ofs, msg_len as usize)?];
+        let file = &data[region(data, file_ofs, file_len as usize)?];
+
+        Err(VMError::ContractPanic {
+            contract: self.contract(),
+            method: self.method.clone(),
+            message: String::from_utf8_lossy(message).into_owned(),
+            location: format!(
+                "{}:{}:{}",
+                String::from_utf8_lossy(file),
+                line as u32,
+                column as u32
+            ),
+        })
+    }
+
+    /// Performs a call to another contract on behalf of the executing one.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn call(
+        &self,
+        caller: &mut dyn Caller,
+        kind: CallKind,
+        id_ofs: i32,
+        name_ofs: i32,
+        name_len: i32,
+        arg_ofs: i32,
+        arg_len: i32,
+        arg_root: i32,
+        ret_ofs: i32,
+    ) -> Result<i32, VMError> {
+        if self.kind == CallKind::Query && kind == CallKind::Apply {
+            return Err(VMError::ApplyInQuery);
+        }
+
+        let (id, name, arg) = {
+            let data = caller.memory();
+
+            let mut id = [0u8; 32];
+            id.copy_from_slice(&data[region(data, id_ofs, 32)?]);
+
+            let name = std::str::from_utf8(&data[region(data, name_ofs, name_len as usize)?])
+                .map_err(|_| VMError::Other("Invalid method name".into()))?
+                .to_string();
+
+            let mut arg = AlignedVec::new();
+            arg.extend_from_slice(&data[region(data, arg_ofs, arg_len as usize)?]);
+
+            (ContractId::from(id), name, arg)
+        };
+
+        let call = RawCall::new(
+            id,
+            &name,
+            &arg,
+            arg_root as u32 as usize,
+            caller.remaining_gas(),
+            kind,
+        )?;
+
+        let receipt = unsafe { State::call_raw(self.state, &self.callers, call)? };
+
+        caller.charge_gas(receipt.gas_used());
+
+        self.events
+            .lock()
+            .expect("poisoned event log")
+            .extend(receipt.events.iter().cloned());
+
+        let data = caller.memory();
+        let ret = region(data, ret_ofs, receipt.len())?;
+        data[ret].copy_from_slice(&receipt);
+
+        Ok(receipt.len() as i32)
+    }
+}
+
+/// Charges the executing contract for work done on its behalf, failing
+/// without charging anything if it cannot afford it.
+pub(crate) fn charge(caller: &mut dyn Caller, gas: u64) -> Result<(), VMError> {
+    if gas > caller.remaining_gas() {
+        return Err(VMError::OutOfGas);
+    }
+    caller.charge_gas(gas);
+    Ok(())
+}
+
+/// Copies the `N` bytes at `ofs` out of contract memory.
+fn array<const N: usize>(data: &[u8], ofs: i32) -> Result<[u8; N], VMError> {
+    let mut array = [0u8; N];
+    array.copy_from_slice(&data[region(data, ofs, N)?]);
+    Ok(array)
+}
+
+/// The range of `len` bytes at `ofs` in contract memory, if it is in bounds.
+pub(crate) fn region(data: &[u8], ofs: i32, len: usize) -> Result<Range<usize>, VMError> {
+    let start = ofs as u32 as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= data.len() => Ok(start..end),
+        _ => Err(VMError::OutOfBounds),
+    }
+}
@@ -0,0 +1,140 @@
+//! Host functions registered by the embedder, offered to contracts alongside
+//! the built-in ones.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::backend::Caller;
+use super::env::{charge, region};
+use super::VMError;
+use crate::definitions::ContractId;
+
+/// The import module of the built-in host functions, off limits to the
+/// embedder.
+pub const HOST_MODULE: &str = "env";
+
+type Function = dyn Fn(&mut HostContext, &[i32]) -> Result<i32, VMError> + Send + Sync;
+
+/// A host function registered by the embedder.
+///
+/// It takes a fixed number of `i32` parameters and returns an `i32`, with
+/// anything larger passed through contract memory.
+#[derive(Clone)]
+pub(crate) struct HostFunction {
+    pub(crate) params: usize,
+    function: Arc<Function>,
+}
+
+impl HostFunction {
+    pub(crate) fn call(
+        &self,
+        caller: &mut dyn Caller,
+        contract: ContractId,
+        args: &[i32],
+    ) -> Result<i32, VMError> {
+        debug_assert_eq!(args.len(), self.params);
+
+        let mut context = HostContext { caller, contract };
+        (self.function)(&mut context, args)
+    }
+}
+
+/// The host functions registered by the embedder, by import module and name.
+#[derive(Clone, Default)]
+pub struct HostFunctions {
+    functions: BTreeMap<(String, String), HostFunction>,
+}
+
+impl HostFunctions {
+    /// Registers `function` as `module.name`, taking `params` parameters and
+    /// replacing any function registered under the same name.
+    pub(crate) fn insert<F>(&mut self, module: &str, name: &str, params: usize, function: F)
+    where
+        F: Fn(&mut HostContext, &[i32]) -> Result<i32, VMError> + Send + Sync + 'static,
+    {
+        let function = HostFunction {
+            params,
+            function: Arc::new(function),
+        };
+        self.functions
+            .insert((module.to_string(), name.to_string()), function);
+    }
+
+    /// Fails if any of the functions is registered in [`HOST_MODULE`].
+    pub(crate) fn validate(&self) -> Result<(), VMError> {
+        match self
+            .functions
+            .keys()
+            .find(|(module, _)| module == HOST_MODULE)
+        {
+            Some((module, name)) => Err(VMError::Other(format!(
+                "Host function {}.{} clashes with the built-in host functions",
+                module, name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &str, &HostFunction)> {
+        self.functions
+            .iter()
+            .map(|((module, name), function)| (module.as_str(), name.as_str(), function))
+    }
+}
+
+impl fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(
+                self.functions
+                    .iter()
+                    .map(|((module, name), function)| (module, name, function.params)),
+            )
+            .finish()
+    }
+}
+
+/// The contract calling a host function registered by the embedder.
+pub struct HostContext<'a> {
+    caller: &'a mut dyn Caller,
+    contract: ContractId,
+}
+
+impl HostContext<'_> {
+    /// The calling contract.
+    pub fn contract(&self) -> ContractId {
+        self.contract
+    }
+
+    /// The memory of the calling contract.
+    pub fn memory(&mut self) -> &mut [u8] {
+        self.caller.memory()
+    }
+
+    /// The `len` bytes of contract memory at `ofs`.
+    pub fn read(&mut self, ofs: i32, len: usize) -> Result<&[u8], VMError> {
+        let data = self.caller.memory();
+        let range = region(data, ofs, len)?;
+        Ok(&data[range])
+    }
+
+    /// Writes `bytes` into contract memory at `ofs`.
+    pub fn write(&mut self, ofs: i32, bytes: &[u8]) -> Result<(), VMError> {
+        let data = self.caller.memory();
+        let range = region(data, ofs, bytes.len())?;
+        data[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// The gas left to the contract.
+    pub fn remaining_gas(&mut self) -> u64 {
+        self.caller.remaining_gas()
+    }
+
+    /// Charges the contract for the work done by the host function, failing
+    /// without charging anything if it cannot afford it.
+    pub fn charge_gas(&mut self, gas: u64) -> Result<(), VMError> {
+        charge(self.caller, gas)
+    }
+}
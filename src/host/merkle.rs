@@ -0,0 +1,235 @@
+//! The Merkle tree the root of a state is computed with.
+//!
+//! Entries are placed in a binary tree by the bits of their path, a hash of
+//! their kind and key, with a subtree holding a single entry collapsed into
+//! that entry. The shape of the tree depends only on the entries it holds, so
+//! the root does not depend on the order they were inserted in, and inserting,
+//! updating or removing an entry rehashes O(log n) nodes.
+
+use crate::definitions::{Caller, ContractId, ACCOUNT_CALLER, CONTRACT_CALLER};
+
+type Hash = [u8; 32];
+
+/// The root of an empty tree.
+const EMPTY: Hash = [0; 32];
+
+// Prefixes keeping the hashes of entries and branches apart.
+const ENTRY_PREFIX: u8 = 0;
+const BRANCH_PREFIX: u8 = 1;
+
+// The kinds of entries, keeping their paths apart.
+const CONTRACT_ENTRY: u8 = 0;
+const BALANCE_ENTRY: u8 = 1;
+
+/// The path of the entry of a contract.
+pub(crate) fn contract_path(id: &ContractId) -> Hash {
+    hash(&[&[CONTRACT_ENTRY], id.as_bytes()])
+}
+
+/// The path of the entry of the balance of an account or contract.
+pub(crate) fn balance_path(owner: &Caller) -> Hash {
+    let (kind, bytes) = match owner {
+        Caller::Account(account) => (ACCOUNT_CALLER, account),
+        Caller::Contract(id) => (CONTRACT_CALLER, id.as_bytes()),
+    };
+    hash(&[&[BALANCE_ENTRY], &kind.to_le_bytes(), bytes])
+}
+
+fn hash(parts: &[&[u8]]) -> Hash {
+    let mut hasher = blake2b_simd::Params::new().hash_length(32).to_state();
+    for part in parts {
+        hasher.update(part);
+    }
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(hasher.finalize().as_bytes());
+    hash
+}
+
+/// The bit of `path` deciding on which side of a branch at `depth` it goes.
+fn bit(path: &Hash, depth: usize) -> usize {
+    ((path[depth / 8] >> (7 - depth % 8)) & 1) as usize
+}
+
+#[derive(Debug)]
+enum Node {
+    Entry {
+        path: Hash,
+        hash: Hash,
+    },
+    // at least two entries are found below a branch
+    Branch {
+        children: [Option<Box<Node>>; 2],
+        hash: Hash,
+    },
+}
+
+impl Node {
+    fn entry(path: Hash, value: &[u8]) -> Box<Self> {
+        let hash = hash(&[&[ENTRY_PREFIX], &path, value]);
+        Box::new(Node::Entry { path, hash })
+    }
+
+    fn branch(children: [Option<Box<Node>>; 2]) -> Box<Self> {
+        let hash = hash(&[
+            &[BRANCH_PREFIX],
+            &Node::hash_of(&children[0]),
+            &Node::hash_of(&children[1]),
+        ]);
+        Box::new(Node::Branch { children, hash })
+    }
+
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Entry { hash, .. } | Node::Branch { hash, .. } => *hash,
+        }
+    }
+
+    fn hash_of(node: &Option<Box<Node>>) -> Hash {
+        node.as_ref().map_or(EMPTY, |node| node.hash())
+    }
+
+    fn is_entry(&self) -> bool {
+        matches!(self, Node::Entry { .. })
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MerkleTree {
+    root: Option<Box<Node>>,
+}
+
+impl MerkleTree {
+    /// The root hash of the tree.
+    pub(crate) fn root(&self) -> Hash {
+        Node::hash_of(&self.root)
+    }
+
+    /// Inserts the entry at `path`, replacing any previous value.
+    pub(crate) fn insert(&mut self, path: Hash, value: &[u8]) {
+        let entry = Node::entry(path, value);
+        self.root = Some(insert(self.root.take(), 0, entry));
+    }
+
+    /// Removes the entry at `path`, if any.
+    pub(crate) fn remove(&mut self, path: &Hash) {
+        self.root = remove(self.root.take(), 0, path);
+    }
+}
+
+fn insert(node: Option<Box<Node>>, depth: usize, entry: Box<Node>) -> Box<Node> {
+    let path = match &*entry {
+        Node::Entry { path, .. } => *path,
+        Node::Branch { .. } => unreachable!("only entries are inserted"),
+    };
+
+    let node = match node {
+        Some(node) => node,
+        None => return entry,
+    };
+
+    match *node {
+        Node::Entry { path: other, .. } if other == path => entry,
+        Node::Entry { path: other, .. } => {
+            // both entries go below a new branch, possibly further down if
+            // their paths share the next bit
+            let mut children = [None, None];
+            if bit(&other, depth) == bit(&path, depth) {
+                children[bit(&path, depth)] = Some(insert(Some(node), depth + 1, entry));
+            } else {
+                children[bit(&other, depth)] = Some(node);
+                children[bit(&path, depth)] = Some(entry);
+            }
+            Node::branch(children)
+        }
+        Node::Branch { mut children, .. } => {
+            let side = bit(&path, depth);
+            children[side] = Some(insert(children[side].take(), depth + 1, entry));
+            Node::branch(children)
+        }
+    }
+}
+
+fn remove(node: Option<Box<Node>>, depth: usize, path: &Hash) -> Option<Box<Node>> {
+    let node = node?;
+
+    match *node {
+        Node::Entry { path: other, .. } if other == *path => None,
+        Node::Entry { .. } => Some(node),
+        Node::Branch { mut children, .. } => {
+            let side = bit(path, depth);
+            children[side] = remove(children[side].take(), depth + 1, path);
+
+            match children {
+                [None, None] => None,
+                // a single entry left below the branch takes its place
+                [Some(child), None] | [None, Some(child)] if child.is_entry() => Some(child),
+                children => Some(Node::branch(children)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(i: u64) -> Hash {
+        hash(&[&i.to_le_bytes()])
+    }
+
+    #[test]
+    fn root_is_independent_of_order() {
+        let n = 100;
+
+        let mut a = MerkleTree::default();
+        let mut b = MerkleTree::default();
+
+        for i in 0..n {
+            a.insert(path(i), &i.to_le_bytes());
+            b.insert(path(n - i - 1), &(n - i - 1).to_le_bytes());
+        }
+        assert_eq!(a.root(), b.root());
+
+        a.insert(path(7), b"changed");
+        assert_ne!(a.root(), b.root());
+        b.insert(path(7), b"changed");
+        assert_eq!(a.root(), b.root());
+    }
+
+    #[test]
+    fn removal_undoes_insertion() {
+        let mut tree = MerkleTree::default();
+        assert_eq!(tree.root(), EMPTY);
+
+        for i in 0..10 {
+            tree.insert(path(i), &i.to_le_bytes());
+        }
+        let root = tree.root();
+
+        tree.insert(path(10), b"extra");
+        assert_ne!(tree.root(), root);
+        tree.remove(&path(10));
+        assert_eq!(tree.root(), root);
+
+        // removing an entry that is not there changes nothing
+        tree.remove(&path(11));
+        assert_eq!(tree.root(), root);
+
+        for i in 0..10 {
+            tree.remove(&path(i));
+        }
+        assert_eq!(tree.root(), EMPTY);
+    }
+
+    #[test]
+    fn kinds_of_entries_are_apart() {
+        let id = ContractId::from([7; 32]);
+
+        assert_ne!(contract_path(&id), balance_path(&Caller::Contract(id)));
+        assert_ne!(
+            balance_path(&Caller::Contract(id)),
+            balance_path(&Caller::Account([7; 32]))
+        );
+    }
+}
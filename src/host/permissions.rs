@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use super::backend::Module;
+use super::VMError;
+
+/// The imports a contract may link against, checked when it is deployed and
+/// whenever it is upgraded.
+///
+/// By default a contract may import anything, be it a built-in host function
+/// or one registered by the embedder.
+///
+/// ```ignore
+/// // only the built-in host functions, and a single one of the embedder
+/// let permissions = Permissions::none()
+///     .allow_module(HOST_MODULE)
+///     .allow_function("chain", "epoch");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Permissions {
+    // by module, the functions allowed or `None` for the whole module, with
+    // `None` allowing any import at all
+    allowed: Option<BTreeSet<(String, Option<String>)>>,
+}
+
+impl Permissions {
+    /// Allows any import.
+    pub fn all() -> Self {
+        Permissions { allowed: None }
+    }
+
+    /// Allows no imports, until some are allowed explicitly.
+    pub fn none() -> Self {
+        Permissions {
+            allowed: Some(BTreeSet::new()),
+        }
+    }
+
+    /// Allows importing anything from `module`.
+    pub fn allow_module(self, module: &str) -> Self {
+        self.allow(module.to_string(), None)
+    }
+
+    /// Allows importing `module.name`.
+    pub fn allow_function(self, module: &str, name: &str) -> Self {
+        self.allow(module.to_string(), Some(name.to_string()))
+    }
+
+    fn allow(mut self, module: String, name: Option<String>) -> Self {
+        if let Some(allowed) = &mut self.allowed {
+            allowed.insert((module, name));
+        }
+        self
+    }
+
+    /// Whether a contract may import `module.name`.
+    pub fn allows(&self, module: &str, name: &str) -> bool {
+        match &self.allowed {
+            None => true,
+            Some(allowed) => allowed
+                .iter()
+                .any(|(m, n)| m == module && n.as_ref().is_none_or(|n| n == name)),
+        }
+    }
+
+    /// Fails on the first import of `module` that is not allowed.
+    pub(crate) fn check(&self, module: &dyn Module) -> Result<(), VMError> {
+        match module
+            .imports()
+            .into_iter()
+            .find(|(module, name)| !self.allows(module, name))
+        {
+            Some((module, name)) => Err(VMError::ForbiddenImport { module, name }),
+            None => Ok(()),
+        }
+    }
+
+    /// The allowed imports, as persisted.
+    pub(crate) fn to_allowed(&self) -> Option<Vec<(String, Option<String>)>> {
+        self.allowed
+            .as_ref()
+            .map(|allowed| allowed.iter().cloned().collect())
+    }
+
+    pub(crate) fn from_allowed(allowed: Option<Vec<(String, Option<String>)>>) -> Self {
+        Permissions {
+            allowed: allowed.map(|allowed| allowed.into_iter().collect()),
+        }
+    }
+}
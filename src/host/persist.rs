@@ -0,0 +1,232 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use rkyv::ser::Serializer;
+use rkyv::{check_archived_root, AlignedVec, Archive, Deserialize, Infallible, Serialize};
+
+use super::cache::CodeHash;
+use super::{ContractInstance, DefaultSerializer, Permissions, State, VMError};
+use crate::definitions::{BlockContext, Caller, ContractId};
+
+// The layout of a state directory:
+//
+// index           the nonce, the states and permissions of all contracts, the
+//                 balances and the block of the latest apply
+// code/<hash>     contract code, by hash
+//
+// Code entries are immutable and written before the index, whose
+// replacement commits the state. A crash at any point leaves either the
+// previous or the new index in place, both referring to complete files.
+const INDEX: &str = "index";
+const CODE_DIR: &str = "code";
+
+#[derive(Archive, Serialize, Deserialize)]
+#[archive_attr(derive(bytecheck::CheckBytes))]
+struct Index {
+    nonce: u64,
+    contracts: Vec<Entry>,
+    balances: Vec<Balance>,
+    block: Block,
+}
+
+#[derive(Archive, Serialize, Deserialize)]
+#[archive_attr(derive(bytecheck::CheckBytes))]
+struct Entry {
+    id: [u8; 32],
+    code_hash: CodeHash,
+    state_ofs: u32,
+    state: Vec<u8>,
+    // the allowed imports, by module and name, with `None` allowing all
+    permissions: Option<Vec<(String, Option<String>)>>,
+}
+
+#[derive(Archive, Serialize, Deserialize)]
+#[archive_attr(derive(bytecheck::CheckBytes))]
+struct Balance {
+    contract: bool,
+    owner: [u8; 32],
+    amount: u64,
+}
+
+#[derive(Archive, Serialize, Deserialize)]
+#[archive_attr(derive(bytecheck::CheckBytes))]
+struct Block {
+    height: u64,
+    timestamp: u64,
+    chain_id: u64,
+}
+
+impl State {
+    /// Opens the state persisted in the directory at `path`, creating an
+    /// empty one if there is none.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, VMError> {
+        State::builder().open(path)
+    }
+
+    /// Loads the state persisted in the directory at `path` into a freshly
+    /// built, empty, state.
+    pub(crate) fn load(mut self, path: &Path) -> Result<Self, VMError> {
+        let path = path.to_path_buf();
+
+        fs::create_dir_all(path.join(CODE_DIR))?;
+
+        self.path = Some(path.clone());
+        let mut state = self;
+
+        let bytes = match fs::read(path.join(INDEX)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(state),
+            Err(e) => return Err(e.into()),
+        };
+
+        // copy the index to ensure it is correctly aligned
+        let mut aligned = AlignedVec::with_capacity(bytes.len());
+        aligned.extend_from_slice(&bytes);
+
+        let index: Index =
+            check_archived_root::<Index>(&aligned[..])?.deserialize(&mut Infallible)?;
+
+        state.nonce = index.nonce;
+        state.block = BlockContext {
+            height: index.block.height,
+            timestamp: index.block.timestamp,
+            chain_id: index.block.chain_id,
+        };
+
+        let mut ids = Vec::with_capacity(index.contracts.len());
+
+        for entry in index.contracts {
+            let code = fs::read(path.join(CODE_DIR).join(hex(&entry.code_hash)))?;
+
+            let mut contract_state = AlignedVec::with_capacity(entry.state.len());
+            contract_state.extend_from_slice(&entry.state);
+
+            let id = ContractId::from(entry.id);
+            state.map.insert(
+                id,
+                ContractInstance {
+                    code,
+                    code_hash: entry.code_hash,
+                    state: contract_state,
+                    state_ofs: entry.state_ofs as i32,
+                    permissions: Permissions::from_allowed(entry.permissions),
+                },
+            );
+            ids.push(id);
+        }
+
+        for balance in index.balances {
+            let owner = if balance.contract {
+                Caller::Contract(ContractId::from(balance.owner))
+            } else {
+                Caller::Account(balance.owner)
+            };
+            state.set_balance(owner, balance.amount);
+        }
+
+        state.update_root(&ids);
+
+        Ok(state)
+    }
+
+    /// Writes the state to the directory it was opened from.
+    pub fn persist(&self) -> Result<(), VMError> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| VMError::Other("State was not opened from a directory".into()))?;
+
+        let mut contracts = Vec::with_capacity(self.map.len());
+
+        for (id, contract) in &self.map {
+            write_once(
+                &path.join(CODE_DIR).join(hex(&contract.code_hash)),
+                &contract.code,
+            )?;
+
+            contracts.push(Entry {
+                id: *id.as_bytes(),
+                code_hash: contract.code_hash,
+                state_ofs: contract.state_ofs as u32,
+                state: contract.state.to_vec(),
+                permissions: contract.permissions.to_allowed(),
+            });
+        }
+
+        // keep the index reproducible
+        contracts.sort_by_key(|entry| entry.id);
+
+        // ordered by owner, like the map they come from
+        let balances = self
+            .balances
+            .iter()
+            .map(|(owner, amount)| {
+                let (contract, owner) = match owner {
+                    Caller::Account(account) => (false, *account),
+                    Caller::Contract(id) => (true, *id.as_bytes()),
+                };
+                Balance {
+                    contract,
+                    owner,
+                    amount: *amount,
+                }
+            })
+            .collect();
+
+        let index = Index {
+            nonce: self.nonce,
+            contracts,
+            balances,
+            block: Block {
+                height: self.block.height,
+                timestamp: self.block.timestamp,
+                chain_id: self.block.chain_id,
+            },
+        };
+
+        let mut serialize = DefaultSerializer::default();
+        serialize.serialize_value(&index)?;
+        let bytes = serialize.into_serializer().into_inner();
+
+        write_atomic(&path.join(INDEX), &bytes)?;
+
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn temporary(path: &Path) -> PathBuf {
+    path.with_extension("tmp")
+}
+
+/// Writes an immutable, content addressed, file unless it already exists.
+fn write_once(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    write_atomic(path, bytes)
+}
+
+/// Replaces the file at `path`, which is left either untouched or with the
+/// full contents, should the process crash midway.
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = temporary(path);
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp, path)?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::ops::Deref;
+
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{check_archived_root, AlignedVec, Archive, Deserialize, Infallible};
+
+use super::VMError;
+use crate::definitions::ContractId;
+
+/// The outcome of a successful contract call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt<R> {
+    pub(crate) ret: R,
+    pub(crate) gas_used: u64,
+    pub(crate) events: Vec<Event>,
+}
+
+impl<R> Receipt<R> {
+    pub(crate) fn new(ret: R, gas_used: u64, events: Vec<Event>) -> Self {
+        Receipt {
+            ret,
+            gas_used,
+            events,
+        }
+    }
+
+    /// Replaces the returned value, keeping the rest of the receipt.
+    pub(crate) fn with_ret<T>(self, ret: T) -> Receipt<T> {
+        Receipt::new(ret, self.gas_used, self.events)
+    }
+
+    /// The gas spent executing the call.
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    /// The events emitted during the call, in order of emission.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Returns the value returned by the contract.
+    pub fn into_inner(self) -> R {
+        self.ret
+    }
+}
+
+impl<R> Deref for Receipt<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.ret
+    }
+}
+
+/// An event emitted by a contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    source: ContractId,
+    topic: String,
+    data: Vec<u8>,
+}
+
+impl Event {
+    pub(crate) fn new(source: ContractId, topic: String, data: Vec<u8>) -> Self {
+        Event {
+            source,
+            topic,
+            data,
+        }
+    }
+
+    /// The contract that emitted the event.
+    pub fn source(&self) -> ContractId {
+        self.source
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// The archived event.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Validates and deserializes the archived event.
+    pub fn decode<E>(&self) -> Result<E, VMError>
+    where
+        E: Archive,
+        E::Archived:
+            for<'a> bytecheck::CheckBytes<DefaultValidator<'a>> + Deserialize<E, Infallible>,
+    {
+        // copy the data to ensure it is correctly aligned
+        let mut data = AlignedVec::with_capacity(self.data.len());
+        data.extend_from_slice(&self.data);
+
+        let archived = check_archived_root::<E>(&data[..])?;
+        Ok(archived.deserialize(&mut Infallible)?)
+    }
+}
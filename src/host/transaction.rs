@@ -0,0 +1,101 @@
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{AlignedVec, Archive, Deserialize, Infallible, Serialize};
+
+use super::{DefaultSerializer, Receipt, State, VMError};
+use crate::definitions::{BlockContext, Caller, ContractId, Method};
+
+/// A set of applies executed against a `State`, committed or rolled back as a
+/// whole.
+///
+/// Each apply is atomic on its own, so one failing does not affect the others.
+/// Dropping the transaction without committing it rolls it back.
+#[derive(Debug)]
+pub struct Transaction<'s> {
+    state: &'s mut State,
+}
+
+impl<'s> Transaction<'s> {
+    pub(crate) fn new(state: &'s mut State) -> Self {
+        debug_assert!(state.journal.is_empty());
+        state.in_transaction = true;
+        Transaction { state }
+    }
+
+    pub fn query<M>(
+        &self,
+        id: ContractId,
+        arg: &M,
+        gas_limit: u64,
+    ) -> Result<Receipt<M::Return>, VMError>
+    where
+        M: Method + Serialize<DefaultSerializer>,
+        M::Return: Archive,
+        <M::Return as Archive>::Archived: for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>
+            + Deserialize<<M as Method>::Return, Infallible>,
+    {
+        self.state.query(id, arg, gas_limit)
+    }
+
+    pub fn query_raw(
+        &self,
+        id: ContractId,
+        name: &str,
+        arg: &[u8],
+        arg_root: usize,
+        gas_limit: u64,
+    ) -> Result<Receipt<AlignedVec>, VMError> {
+        self.state.query_raw(id, name, arg, arg_root, gas_limit)
+    }
+
+    pub fn apply<M>(
+        &mut self,
+        id: ContractId,
+        arg: &M,
+        caller: Caller,
+        value: u64,
+        block: BlockContext,
+        gas_limit: u64,
+    ) -> Result<Receipt<M::Return>, VMError>
+    where
+        M: Method + Serialize<DefaultSerializer>,
+        M::Return: Archive,
+        <M::Return as Archive>::Archived: for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>
+            + Deserialize<<M as Method>::Return, Infallible>,
+    {
+        self.state.apply(id, arg, caller, value, block, gas_limit)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_raw(
+        &mut self,
+        id: ContractId,
+        name: &str,
+        arg: &[u8],
+        arg_root: usize,
+        caller: Caller,
+        value: u64,
+        block: BlockContext,
+        gas_limit: u64,
+    ) -> Result<Receipt<AlignedVec>, VMError> {
+        self.state
+            .apply_raw(id, name, arg, arg_root, caller, value, block, gas_limit)
+    }
+
+    /// Keeps all the changes made in the transaction.
+    pub fn commit(self) {
+        self.state.journal.clear();
+    }
+
+    /// Discards all the changes made in the transaction.
+    pub fn rollback(self) {
+        // rolling back is done on drop
+    }
+}
+
+impl<'s> Drop for Transaction<'s> {
+    fn drop(&mut self) {
+        // a no-op after a commit, since the journal is empty
+        self.state.revert(0);
+        self.state.in_transaction = false;
+    }
+}
@@ -4,6 +4,8 @@
 mod definitions;
 pub use definitions::*;
 
+pub use vm_proto_macros::{contract, method};
+
 #[cfg(feature = "host")]
 mod host;
 
@@ -1,7 +1,50 @@
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
 
+use crate::abi::ext;
+
+/// The longest panic message passed on to the host, longer ones are truncated.
+const PANIC_BUFFER_SIZE: usize = 1024;
+
+struct PanicBuffer {
+    buf: [u8; PANIC_BUFFER_SIZE],
+    len: usize,
+}
+
+impl Write for PanicBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(PANIC_BUFFER_SIZE - self.len);
+        self.buf[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
+    let mut msg = PanicBuffer {
+        buf: [0; PANIC_BUFFER_SIZE],
+        len: 0,
+    };
+    let _ = write!(msg, "{}", info.message());
+
+    let (file, line, column) = match info.location() {
+        Some(loc) => (loc.file(), loc.line(), loc.column()),
+        None => ("<unknown>", 0, 0),
+    };
+
+    unsafe {
+        ext::panic(
+            msg.buf.as_ptr(),
+            msg.len as i32,
+            file.as_ptr(),
+            file.len() as i32,
+            line as i32,
+            column as i32,
+        )
+    };
+
+    // the host aborts execution, this is never reached
     loop {}
 }
 
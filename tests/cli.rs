@@ -0,0 +1,71 @@
+//! The command line interface, run against a state in a temporary directory.
+#![cfg(feature = "host")]
+
+use std::path::Path;
+use std::process::Command;
+
+/// Keeps a `u64` in the state, bumped by one at a time.
+const COUNTER: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "total") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (i64.load (local.get $s)))
+    (i32.const 8))
+  (func (export "bump") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $s) (i64.add (i64.load (local.get $s)) (i64.const 1)))
+    (i32.const 0))
+)"#;
+
+/// Runs the command line interface on `dir`, returning what it printed.
+fn vm_proto(dir: &Path, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new(env!("CARGO_BIN_EXE_vm-proto"))
+        .arg(dir)
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "vm-proto {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[test]
+fn deploy_list_and_call() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("vm-proto-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let code = dir.join("counter.wasm");
+    std::fs::write(&code, wat::parse_str(COUNTER)?)?;
+
+    let id = vm_proto(
+        &dir,
+        &["deploy", code.to_str().unwrap(), "0700000000000000"],
+    )?;
+    let id = id.trim();
+    assert_eq!(id.len(), 64, "{}", id);
+
+    assert_eq!(vm_proto(&dir, &["list"])?, format!("{} 0\n", id));
+
+    vm_proto(&dir, &["apply", id, "bump", "--caller", &"01".repeat(32)])?;
+    assert_eq!(
+        vm_proto(&dir, &["query", id, "total"])?,
+        "0800000000000000\n"
+    );
+
+    // calls that fail leave the state as it was
+    assert!(vm_proto(&dir, &["apply", id, "bump", "--gas", "1"]).is_err());
+    assert!(vm_proto(&dir, &["query", id, "missing"]).is_err());
+    assert_eq!(
+        vm_proto(&dir, &["query", id, "total"])?,
+        "0800000000000000\n"
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+//! The gas limit, caller and block shared by the integration tests.
+
+// each test crate compiles its own copy, not all of which use every item
+#![allow(dead_code)]
+
+use vm_proto::{BlockContext, Caller};
+
+pub const GAS_LIMIT: u64 = 1_000_000;
+
+pub const CALLER: Caller = Caller::Account([1; 32]);
+
+pub const BLOCK: BlockContext = BlockContext {
+    height: 1,
+    timestamp: 1_600_000_000,
+    chain_id: 1,
+};
@@ -0,0 +1,539 @@
+//! Host behaviour that well-behaved contracts never run into, exercised with
+//! hand-written modules.
+
+use rkyv::{Archive, Serialize};
+use vm_proto::*;
+
+mod common;
+use common::{BLOCK, CALLER, GAS_LIMIT};
+
+/// Keeps a `u64` in the state, bumped by one at a time.
+const COUNTER: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "total") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (i64.load (local.get $s)))
+    (i32.const 8))
+  (func (export "bump") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $s) (i64.add (i64.load (local.get $s)) (i64.const 1)))
+    (i32.const 0))
+)"#;
+
+/// The counter, with a migration setting it to 1 that reports the root of the
+/// 8 migrated bytes at `{root}`.
+const MIGRATING: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "total") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (i64.load (local.get $s)))
+    (i32.const 8))
+  (func (export "migrate") (param $s i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (i64.const 1))
+    (i32.store (i32.add (local.get $r) (i32.const 8)) (i32.const {root}))
+    (i32.const 12))
+)"#;
+
+/// Tells the height of the block it is called in, failing when asked to.
+const HEIGHT: &str = r#"(module
+  (import "env" "block_height" (func $block_height (result i64)))
+  (memory (export "memory") 1)
+  (func (export "height") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (call $block_height))
+    (i32.const 8))
+  (func (export "fail") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    unreachable)
+)"#;
+
+/// Hashes as many bytes at the start of its memory as it is asked to.
+const HASHER: &str = r#"(module
+  (import "env" "blake2b" (func $blake2b (param i32 i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "hash") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (call $blake2b (i32.const 0) (i32.load (local.get $a)) (local.get $r))
+    (i32.const 32))
+)"#;
+
+/// Prints as many bytes at the given offset as it is asked to.
+const DEBUG: &str = r#"(module
+  (import "env" "debug" (func $debug (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "\ff\fe not UTF-8")
+  (func (export "print") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (call $debug (i32.load (local.get $a)) (i32.load offset=4 (local.get $a)))
+    (i32.const 0))
+)"#;
+
+/// Asks the embedder for the epoch, and to write the id of the contract.
+const CHAIN: &str = r#"(module
+  (import "chain" "epoch" (func $epoch (result i32)))
+  (import "chain" "whoami" (func $whoami (param i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "epoch") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (i64.extend_i32_u (call $epoch)))
+    (i32.const 8))
+  (func (export "whoami") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (drop (call $whoami (local.get $r)))
+    (i32.const 32))
+)"#;
+
+/// Greets with an archived `Vec<u8>`, its bytes followed by its root.
+const GREETER: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "greet") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (i64.store (local.get $r) (i64.const 0x6f6c6c6568))
+    (i32.store offset=8 (local.get $r) (i32.const -8))
+    (i32.store offset=12 (local.get $r) (i32.const 5))
+    (i32.const 16))
+)"#;
+
+/// Emits as many bytes at the start of its memory as it is asked to.
+const EMITTER: &str = r#"(module
+  (import "env" "emit" (func $emit (param i32 i32 i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "topic")
+  (func (export "shout") (param $s i32) (param $a i32) (param $r i32) (result i32)
+    (call $emit (i32.const 0) (i32.const 5) (i32.const 0) (i32.load (local.get $a)))
+    (i32.const 0))
+)"#;
+
+#[derive(Archive, Serialize, Debug)]
+struct Total;
+
+impl Method for Total {
+    const NAME: &'static str = "total";
+    type Return = u64;
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Bump;
+
+impl Method for Bump {
+    const NAME: &'static str = "bump";
+    type Return = ();
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Height;
+
+impl Method for Height {
+    const NAME: &'static str = "height";
+    type Return = u64;
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Fail;
+
+impl Method for Fail {
+    const NAME: &'static str = "fail";
+    type Return = ();
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Hash(u32);
+
+impl Method for Hash {
+    const NAME: &'static str = "hash";
+    type Return = [u8; 32];
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Print {
+    ofs: u32,
+    len: u32,
+}
+
+impl Method for Print {
+    const NAME: &'static str = "print";
+    type Return = ();
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Epoch;
+
+impl Method for Epoch {
+    const NAME: &'static str = "epoch";
+    type Return = u64;
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct WhoAmI;
+
+impl Method for WhoAmI {
+    const NAME: &'static str = "whoami";
+    type Return = [u8; 32];
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Greet;
+
+impl Method for Greet {
+    const NAME: &'static str = "greet";
+    type Return = Vec<u8>;
+}
+
+#[derive(Archive, Serialize, Debug)]
+struct Shout(u32);
+
+impl Method for Shout {
+    const NAME: &'static str = "shout";
+    type Return = ();
+}
+
+fn code(wat: &str) -> Vec<u8> {
+    wat::parse_str(wat).expect("valid module")
+}
+
+#[test]
+fn variable_sized_returns() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy((), code(GREETER))?;
+
+    assert_eq!(*state.query(id, &Greet, GAS_LIMIT)?, b"hello");
+    assert_eq!(
+        *state.apply(id, &Greet, CALLER, 0, BLOCK, GAS_LIMIT)?,
+        b"hello"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn migration_with_bad_root_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(7u64, code(COUNTER))?;
+    let root = state.root();
+
+    let bad_root = code(&MIGRATING.replace("{root}", "100"));
+    match state.upgrade(id, bad_root, GAS_LIMIT) {
+        Err(VMError::InvalidReturnArchive {
+            contract, method, ..
+        }) => {
+            assert_eq!(contract, id);
+            assert_eq!(method, MIGRATE_EXPORT);
+        }
+        other => panic!("expected an invalid migration, got {:?}", other.map(|_| ())),
+    }
+
+    assert_eq!(state.root(), root);
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 7);
+
+    state.upgrade(id, code(&MIGRATING.replace("{root}", "0")), GAS_LIMIT)?;
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn state_mutated_in_query_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(7u64, code(COUNTER))?;
+    let root = state.root();
+
+    match state.query(id, &Bump, GAS_LIMIT) {
+        Err(VMError::StateMutatedInQuery(contract)) => assert_eq!(contract, id),
+        other => panic!("expected a mutated state, got {:?}", other.map(|_| ())),
+    }
+
+    assert_eq!(state.root(), root);
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 7);
+
+    // the same method is fine in an apply
+    state.apply(id, &Bump, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 8);
+
+    Ok(())
+}
+
+#[test]
+fn failed_apply_keeps_previous_block() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy((), code(HEIGHT))?;
+
+    state.apply(id, &Height, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(*state.query(id, &Height, GAS_LIMIT)?, 1);
+
+    let next = BlockContext { height: 2, ..BLOCK };
+    assert!(state.apply(id, &Fail, CALLER, 0, next, GAS_LIMIT).is_err());
+    assert_eq!(*state.query(id, &Height, GAS_LIMIT)?, 1);
+
+    // nor does a rolled back transaction move to its block
+    let mut transaction = state.transaction();
+    transaction.apply(id, &Height, CALLER, 0, next, GAS_LIMIT)?;
+    assert_eq!(*transaction.query(id, &Height, GAS_LIMIT)?, 2);
+    transaction.rollback();
+    assert_eq!(*state.query(id, &Height, GAS_LIMIT)?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn contract_caller_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(7u64, code(COUNTER))?;
+    let root = state.root();
+
+    let caller = Caller::Contract(id);
+    match state.apply(id, &Bump, caller, 0, BLOCK, GAS_LIMIT) {
+        Err(VMError::ContractCaller(contract)) => assert_eq!(contract, id),
+        other => panic!("expected a contract caller, got {:?}", other.map(|_| ())),
+    }
+
+    assert_eq!(state.root(), root);
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 7);
+
+    Ok(())
+}
+
+#[test]
+fn spoofed_caller_cannot_drain_contract() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let rich = state.deploy(7u64, code(COUNTER))?;
+    let thief = state.deploy(0u64, code(COUNTER))?;
+
+    state.deposit(Caller::Contract(rich), 100)?;
+
+    let spoofed = Caller::Contract(rich);
+    match state.apply(thief, &Bump, spoofed, 100, BLOCK, GAS_LIMIT) {
+        Err(VMError::ContractCaller(contract)) => assert_eq!(contract, rich),
+        other => panic!("expected a contract caller, got {:?}", other.map(|_| ())),
+    }
+
+    assert_eq!(state.balance(&Caller::Contract(rich)), 100);
+    assert_eq!(state.balance(&Caller::Contract(thief)), 0);
+
+    // nor within a transaction
+    let mut transaction = state.transaction();
+    assert!(transaction
+        .apply(thief, &Bump, spoofed, 100, BLOCK, GAS_LIMIT)
+        .is_err());
+    transaction.commit();
+
+    assert_eq!(state.balance(&Caller::Contract(rich)), 100);
+    assert_eq!(state.balance(&Caller::Contract(thief)), 0);
+
+    Ok(())
+}
+
+#[test]
+fn crypto_is_charged_for() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy((), code(HASHER))?;
+
+    // the Blake2b hash of nothing
+    let empty = state.query(id, &Hash(0), GAS_LIMIT)?;
+    assert_eq!(
+        *empty,
+        [
+            0x0e, 0x57, 0x51, 0xc0, 0x26, 0xe5, 0x43, 0xb2, 0xe8, 0xab, 0x2e, 0xb0, 0x60, 0x99,
+            0xda, 0xa1, 0xd1, 0xe5, 0xdf, 0x47, 0x77, 0x8f, 0x77, 0x87, 0xfa, 0xab, 0x45, 0xcd,
+            0xf1, 0x2f, 0xe3, 0xa8
+        ]
+    );
+
+    // each byte hashed costs the same, on top of the same instructions
+    let long = state.query(id, &Hash(1_000), GAS_LIMIT)?;
+    assert_eq!(long.gas_used() - empty.gas_used(), 1_000);
+
+    match state.query(id, &Hash(1_000), empty.gas_used() + 999) {
+        Err(VMError::OutOfGas) => Ok(()),
+        other => panic!("expected to run out of gas, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn events_are_charged_for() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy((), code(EMITTER))?;
+
+    let quiet = state.apply(id, &Shout(0), CALLER, 0, BLOCK, GAS_LIMIT)?;
+    let loud = state.apply(id, &Shout(1_000), CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(loud.events()[0].topic(), "topic");
+    assert_eq!(loud.events()[0].data().len(), 1_000);
+
+    // each byte emitted costs the same, on top of the same instructions
+    assert_eq!(loud.gas_used() - quiet.gas_used(), 1_000);
+
+    let gas_limit = quiet.gas_used() + 999;
+    match state.apply(id, &Shout(1_000), CALLER, 0, BLOCK, gas_limit) {
+        Err(VMError::OutOfGas) => Ok(()),
+        other => panic!("expected to run out of gas, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn debug_out_of_bounds_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy((), code(DEBUG))?;
+
+    // invalid UTF-8 is printed all the same
+    state.query(id, &Print { ofs: 0, len: 14 }, GAS_LIMIT)?;
+
+    let past_memory = Print {
+        ofs: u32::MAX - 4,
+        len: 8,
+    };
+    match state.query(id, &past_memory, GAS_LIMIT) {
+        Err(VMError::OutOfBounds) => (),
+        other => panic!(
+            "expected an out of bounds access, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+
+    let wrapping = Print {
+        ofs: u32::MAX,
+        len: u32::MAX,
+    };
+    match state.query(id, &wrapping, GAS_LIMIT) {
+        Err(VMError::OutOfBounds) => Ok(()),
+        other => panic!(
+            "expected an out of bounds access, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
+
+#[test]
+fn host_functions_reach_contracts() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::builder()
+        .host_function("chain", "epoch", 0, |_, _| Ok(7))
+        .host_function("chain", "whoami", 1, |context, args| {
+            let id = context.contract();
+            context.charge_gas(1_000)?;
+            context.write(args[0], id.as_bytes())?;
+            Ok(0)
+        })
+        .build()?;
+    let id = state.deploy((), code(CHAIN))?;
+
+    assert_eq!(*state.query(id, &Epoch, GAS_LIMIT)?, 7);
+
+    let whoami = state.query(id, &WhoAmI, GAS_LIMIT)?;
+    assert_eq!(*whoami, *id.as_bytes());
+    assert!(whoami.gas_used() > 1_000, "{}", whoami.gas_used());
+
+    // contracts not importing the functions are unaffected by them
+    let id = state.deploy(7u64, code(COUNTER))?;
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 7);
+
+    // a state without the functions cannot run the contract
+    let mut state = State::default();
+    let id = state.deploy((), code(CHAIN))?;
+    assert!(state.query(id, &Epoch, GAS_LIMIT).is_err());
+
+    // the built-in host functions cannot be replaced
+    assert!(State::builder()
+        .host_function(HOST_MODULE, "debug", 2, |_, _| Ok(0))
+        .build()
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn forbidden_imports_are_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let permissions = Permissions::none()
+        .allow_module(HOST_MODULE)
+        .allow_function("chain", "epoch");
+
+    assert!(permissions.allows(HOST_MODULE, "block_height"));
+    assert!(permissions.allows("chain", "epoch"));
+    assert!(!permissions.allows("chain", "whoami"));
+    assert!(Permissions::all().allows("chain", "whoami"));
+
+    let mut state = State::default();
+    let id = state.deploy_with_permissions((), code(HEIGHT), permissions)?;
+    state.apply(id, &Height, CALLER, 0, BLOCK, GAS_LIMIT)?;
+
+    let root = state.root();
+    match state.deploy_with_permissions(0u64, code(HEIGHT), Permissions::none()) {
+        Err(VMError::ForbiddenImport { module, name }) => {
+            assert_eq!(module, HOST_MODULE);
+            assert_eq!(name, "block_height");
+        }
+        other => panic!("expected a forbidden import, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(state.root(), root);
+
+    // the permissions a contract is deployed with also hold its upgrades
+    let id = state.deploy_with_permissions(7u64, code(COUNTER), Permissions::none())?;
+    let root = state.root();
+
+    match state.upgrade(id, code(HEIGHT), GAS_LIMIT) {
+        Err(VMError::ForbiddenImport { module, name }) => {
+            assert_eq!(module, HOST_MODULE);
+            assert_eq!(name, "block_height");
+        }
+        other => panic!("expected a forbidden import, got {:?}", other.map(|_| ())),
+    }
+
+    assert_eq!(state.root(), root);
+    assert_eq!(*state.query(id, &Total, GAS_LIMIT)?, 7);
+
+    Ok(())
+}
+
+#[test]
+fn ids_keep_code_and_state_apart() -> Result<(), Box<dyn std::error::Error>> {
+    // an empty custom section, valid at the end of a module and as state alike
+    let section = [0, 2, 1, b'x'];
+
+    let code = code(COUNTER);
+    let mut longer_code = code.clone();
+    longer_code.extend_from_slice(&section);
+
+    let state = 7u64.to_le_bytes();
+    let mut longer_state = section.to_vec();
+    longer_state.extend_from_slice(&state);
+
+    let a = State::default().deploy_raw(&longer_state, 4, code, Permissions::all())?;
+    let b = State::default().deploy_raw(&state, 0, longer_code, Permissions::all())?;
+    assert_ne!(a, b);
+
+    Ok(())
+}
+
+#[test]
+fn roots_tell_state_roots_apart() -> Result<(), Box<dyn std::error::Error>> {
+    // two counters in one state, either of which may be its root
+    let mut state = 7u64.to_le_bytes().to_vec();
+    state.extend_from_slice(&8u64.to_le_bytes());
+
+    let mut first = State::default();
+    let id = first.deploy_raw(&state, 0, code(COUNTER), Permissions::all())?;
+    let mut second = State::default();
+    second.deploy_raw(&state, 8, code(COUNTER), Permissions::all())?;
+
+    assert_eq!(*first.query(id, &Total, GAS_LIMIT)?, 7);
+    assert_eq!(*second.query(id, &Total, GAS_LIMIT)?, 8);
+    assert_ne!(first.root(), second.root());
+
+    Ok(())
+}
+
+#[test]
+fn block_outlives_reopening() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("host-block-{}", std::process::id()));
+    let next = BlockContext { height: 2, ..BLOCK };
+
+    let id = {
+        let mut state = State::open(&dir)?;
+        let id = state.deploy((), code(HEIGHT))?;
+        state.apply(id, &Height, CALLER, 0, next, GAS_LIMIT)?;
+        state.persist()?;
+        id
+    };
+
+    let state = State::open(&dir)?;
+    assert_eq!(*state.query(id, &Height, GAS_LIMIT)?, 2);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn empty_module_cache_is_rejected() {
+    match State::builder().module_cache_capacity(0).build() {
+        Err(VMError::Other(reason)) => assert!(reason.contains("capacity"), "{}", reason),
+        other => panic!("expected an error, got {:?}", other.map(|_| ())),
+    }
+}
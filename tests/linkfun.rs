@@ -2,6 +2,9 @@ use std::pin::Pin;
 
 use vm_proto::*;
 
+mod common;
+use common::{BLOCK, CALLER, GAS_LIMIT};
+
 use funlink::{FunLink, Pop, Push};
 
 const CODE: &'static [u8] =
@@ -33,17 +36,26 @@ fn query_deployed_contract() -> Result<(), Box<dyn std::error::Error>> {
 
     let id = state.deploy(fun, CODE)?;
 
-    assert_eq!(state.apply(id, &Pop)?, Ok(None));
+    assert_eq!(
+        *state.apply(id, &Pop, CALLER, 0, BLOCK, GAS_LIMIT)?,
+        Ok(None)
+    );
 
     for i in 0..N {
-        state.apply(id, &Push(i))?;
+        state.apply(id, &Push(i), CALLER, 0, BLOCK, GAS_LIMIT)?;
     }
 
     for i in 0..N {
-        assert_eq!(state.apply(id, &Pop)?, Ok(Some(N - i - 1)))
+        assert_eq!(
+            *state.apply(id, &Pop, CALLER, 0, BLOCK, GAS_LIMIT)?,
+            Ok(Some(N - i - 1))
+        )
     }
 
-    assert_eq!(state.apply(id, &Pop)?, Ok(None));
+    assert_eq!(
+        *state.apply(id, &Pop, CALLER, 0, BLOCK, GAS_LIMIT)?,
+        Ok(None)
+    );
 
     Ok(())
 }
@@ -0,0 +1,147 @@
+use vm_proto::*;
+
+mod common;
+use common::{BLOCK, CALLER};
+
+use lobbyist::{Bribe, Campaign, Lobby, LobbyInQuery, Lobbyist, Pitch, Reenter, Supply};
+use plutocracy::{Plutocracy, TotalSupply};
+
+const PLUTOCRACY: &[u8] =
+    include_bytes!("../contracts/plutocracy/target/wasm32-unknown-unknown/release/plutocracy.wasm");
+
+const CODE: &[u8] =
+    include_bytes!("../contracts/lobbyist/target/wasm32-unknown-unknown/release/lobbyist.wasm");
+
+const GAS_LIMIT: u64 = 10_000_000;
+
+fn deploy(state: &mut State) -> Result<(ContractId, ContractId), VMError> {
+    let pluto = state.deploy(Plutocracy::new(), PLUTOCRACY)?;
+    let lobbyist = state.deploy(Lobbyist::new(pluto), CODE)?;
+    Ok((pluto, lobbyist))
+}
+
+#[test]
+fn query_through_another_contract() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let (pluto, lobbyist) = deploy(&mut state)?;
+
+    assert_eq!(*state.query(lobbyist, &Supply, GAS_LIMIT)?, 0);
+
+    state.apply(
+        pluto,
+        &plutocracy::Mint { amount: 5 },
+        CALLER,
+        0,
+        BLOCK,
+        GAS_LIMIT,
+    )?;
+    assert_eq!(*state.query(lobbyist, &Supply, GAS_LIMIT)?, 5);
+
+    Ok(())
+}
+
+#[test]
+fn apply_through_another_contract() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let (pluto, lobbyist) = deploy(&mut state)?;
+
+    let receipt = state.apply(lobbyist, &Lobby { amount: 10 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(*receipt, 10);
+
+    // the event emitted by the plutocracy ends up in the receipt
+    assert_eq!(receipt.events().len(), 1);
+    assert_eq!(receipt.events()[0].source(), pluto);
+    assert_eq!(receipt.events()[0].topic(), "mint");
+
+    assert_eq!(*state.query(pluto, &TotalSupply, GAS_LIMIT)?, 10);
+
+    Ok(())
+}
+
+#[test]
+fn variable_sized_returns() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let (pluto, lobbyist) = deploy(&mut state)?;
+
+    let pitch = state.query(lobbyist, &Pitch { amount: 1234 }, GAS_LIMIT)?;
+    assert_eq!(*pitch, "please mint 1234");
+
+    let campaign = Campaign {
+        amount: 5,
+        rounds: 3,
+    };
+    let receipt = state.apply(lobbyist, &campaign, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(*receipt, vec![5, 10, 15]);
+
+    let campaign = Campaign {
+        amount: 1,
+        rounds: 0,
+    };
+    let receipt = state.apply(lobbyist, &campaign, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert!(receipt.is_empty());
+
+    assert_eq!(*state.query(pluto, &TotalSupply, GAS_LIMIT)?, 15);
+
+    Ok(())
+}
+
+#[test]
+fn apply_in_query_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let (pluto, lobbyist) = deploy(&mut state)?;
+
+    match state.query(lobbyist, &LobbyInQuery { amount: 10 }, GAS_LIMIT) {
+        Err(VMError::ApplyInQuery) => (),
+        other => panic!("expected an apply in a query, got {:?}", other.map(|_| ())),
+    }
+
+    assert_eq!(*state.query(pluto, &TotalSupply, GAS_LIMIT)?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn reentrancy_is_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let (_, lobbyist) = deploy(&mut state)?;
+    let (_, other) = deploy(&mut state)?;
+
+    // calling another lobbyist is fine
+    let reenter = Reenter {
+        lobbyist: *other.as_bytes(),
+    };
+    assert_eq!(*state.query(lobbyist, &reenter, GAS_LIMIT)?, 0);
+
+    let reenter = Reenter {
+        lobbyist: *lobbyist.as_bytes(),
+    };
+    match state.query(lobbyist, &reenter, GAS_LIMIT) {
+        Err(VMError::Reentrancy(id)) => assert_eq!(id, lobbyist),
+        other => panic!("expected a reentrant call, got {:?}", other.map(|_| ())),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn contract_panic() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let (_, lobbyist) = deploy(&mut state)?;
+
+    match state.apply(lobbyist, &Bribe { amount: 3 }, CALLER, 0, BLOCK, GAS_LIMIT) {
+        Err(VMError::ContractPanic {
+            contract,
+            method,
+            message,
+            location,
+        }) => {
+            assert_eq!(contract, lobbyist);
+            assert_eq!(method, "bribe");
+            assert_eq!(message, "refused a bribe of 3");
+            assert!(location.starts_with("src/lib.rs:"), "{}", location);
+        }
+        other => panic!("expected a panic, got {:?}", other.map(|_| ())),
+    }
+
+    Ok(())
+}
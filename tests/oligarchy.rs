@@ -0,0 +1,77 @@
+use vm_proto::*;
+
+mod common;
+use common::{BLOCK, CALLER, GAS_LIMIT};
+
+use oligarchy::{Mints, MAX_TREASURY};
+use plutocracy::{Mint, Plutocracy, TotalSupply};
+
+const PLUTOCRACY: &[u8] =
+    include_bytes!("../contracts/plutocracy/target/wasm32-unknown-unknown/release/plutocracy.wasm");
+
+const CODE: &[u8] =
+    include_bytes!("../contracts/oligarchy/target/wasm32-unknown-unknown/release/oligarchy.wasm");
+
+#[test]
+fn upgrade_with_migration() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), PLUTOCRACY)?;
+
+    state.apply(id, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    let root = state.root();
+
+    let receipt = state.upgrade(id, CODE, GAS_LIMIT)?;
+    assert!(receipt.gas_used() > 0);
+    assert_ne!(state.root(), root);
+
+    // the treasury carries over into the new layout
+    assert_eq!(*state.query(id, &oligarchy::TotalSupply, GAS_LIMIT)?, 100);
+    assert_eq!(*state.query(id, &Mints, GAS_LIMIT)?, 0);
+
+    state.apply(
+        id,
+        &oligarchy::Mint { amount: 5 },
+        CALLER,
+        0,
+        BLOCK,
+        GAS_LIMIT,
+    )?;
+    assert_eq!(*state.query(id, &oligarchy::TotalSupply, GAS_LIMIT)?, 105);
+    assert_eq!(*state.query(id, &Mints, GAS_LIMIT)?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn failed_migration_leaves_contract_untouched() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), PLUTOCRACY)?;
+
+    let amount = MAX_TREASURY + 1;
+    state.apply(id, &Mint { amount }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    let root = state.root();
+
+    match state.upgrade(id, CODE, GAS_LIMIT) {
+        Err(VMError::ContractPanic {
+            contract,
+            method,
+            message,
+            ..
+        }) => {
+            assert_eq!(contract, id);
+            assert_eq!(method, "migrate");
+            assert!(message.contains("too large to migrate"), "{}", message)
+        }
+        other => panic!("expected a panic, got {:?}", other.map(|_| ())),
+    }
+
+    // both the code and the state are the ones of the plutocracy
+    assert_eq!(state.root(), root);
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, amount);
+    match state.query(id, &Mints, GAS_LIMIT) {
+        Err(VMError::MethodNotFound { method, .. }) => assert_eq!(method, "mints"),
+        other => panic!("expected a missing method, got {:?}", other.map(|_| ())),
+    }
+
+    Ok(())
+}
@@ -2,6 +2,9 @@ use std::pin::Pin;
 
 use vm_proto::*;
 
+mod common;
+use common::{BLOCK, CALLER, GAS_LIMIT};
+
 use plutocracy::{Mint, Plutocracy, TotalSupply};
 
 const CODE: &'static [u8] =
@@ -18,6 +21,24 @@ fn contract_standalone() {
     assert_eq!(pluto.query(&TotalSupply), 100);
 }
 
+#[test]
+fn contract_standalone_events() -> Result<(), Box<dyn std::error::Error>> {
+    let mut pluto = Plutocracy::new();
+
+    Pin::new(&mut pluto).apply(&Mint { amount: 100 });
+    Pin::new(&mut pluto).apply(&Mint { amount: 5 });
+
+    let events = abi::take_events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].topic(), "mint");
+    assert_eq!(events[0].decode::<u64>()?, 100);
+    assert_eq!(events[1].decode::<u64>()?, 5);
+
+    assert!(abi::take_events().is_empty());
+
+    Ok(())
+}
+
 #[test]
 fn query_deployed_contract() -> Result<(), Box<dyn std::error::Error>> {
     let n = 201;
@@ -28,7 +49,7 @@ fn query_deployed_contract() -> Result<(), Box<dyn std::error::Error>> {
 
     let id = state.deploy(pluto, CODE)?;
 
-    assert_eq!(state.query(id, &TotalSupply).unwrap(), n);
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT).unwrap(), n);
 
     Ok(())
 }
@@ -39,11 +60,275 @@ fn transact_deployed_contract() -> Result<(), Box<dyn std::error::Error>> {
     let pluto = Plutocracy::new();
     let id = state.deploy(pluto, CODE)?;
 
-    assert_eq!(state.query(id, &TotalSupply)?, 0);
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 0);
+
+    state.apply(id, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT).unwrap(), 100);
+
+    Ok(())
+}
+
+#[test]
+fn deploy_multiple_instances() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+
+    let a = state.deploy(Plutocracy::new(), CODE)?;
+    let b = state.deploy(Plutocracy::new(), CODE)?;
+
+    assert_ne!(a, b);
+
+    state.apply(a, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+
+    assert_eq!(*state.query(a, &TotalSupply, GAS_LIMIT)?, 100);
+    assert_eq!(*state.query(b, &TotalSupply, GAS_LIMIT)?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn contract_ids_are_reproducible() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state_a = State::default();
+    let mut state_b = State::default();
+
+    for _ in 0..2 {
+        assert_eq!(
+            state_a.deploy(Plutocracy::new(), CODE)?,
+            state_b.deploy(Plutocracy::new(), CODE)?
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn raw_calls() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), CODE)?;
+
+    // an archived `Mint` is just its little endian amount
+    let mint = 100u64.to_le_bytes();
+    state.apply_raw(id, "mint", &mint, 0, CALLER, 0, BLOCK, GAS_LIMIT)?;
+
+    let supply = state.query_raw(id, "total_supply", &[], 0, GAS_LIMIT)?;
+    assert_eq!(&supply[..], &100u64.to_le_bytes());
+
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 100);
+
+    Ok(())
+}
+
+#[test]
+fn state_larger_than_initial_memory() -> Result<(), Box<dyn std::error::Error>> {
+    // an archived `Plutocracy` preceded by more bytes than the contract has
+    // memory to begin with, which must not clobber its stack or statics
+    let padding = 4 * 1024 * 1024;
+    let mut state_bytes = vec![0xaa; padding];
+    state_bytes.extend_from_slice(&7u64.to_le_bytes());
+
+    let mut state = State::default();
+    let id = state.deploy_raw(&state_bytes, padding, CODE, Permissions::all())?;
+
+    state.apply(id, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 107);
+
+    Ok(())
+}
+
+#[test]
+fn apply_with_value() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), CODE)?;
+
+    state.deposit(CALLER, 100)?;
+    state.apply(id, &Mint { amount: 1 }, CALLER, 30, BLOCK, GAS_LIMIT)?;
+
+    assert_eq!(state.balance(&CALLER), 70);
+    assert_eq!(state.balance(&Caller::Contract(id)), 30);
+
+    // a failed apply returns the value
+    assert!(state
+        .apply(id, &Mint { amount: 1 }, CALLER, 30, BLOCK, 1)
+        .is_err());
+
+    assert_eq!(state.balance(&CALLER), 70);
+    assert_eq!(state.balance(&Caller::Contract(id)), 30);
+
+    match state.apply(id, &Mint { amount: 1 }, CALLER, 1000, BLOCK, GAS_LIMIT) {
+        Err(VMError::InsufficientBalance { .. }) => Ok(()),
+        other => panic!("expected insufficient balance, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn call_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), CODE)?;
+
+    match state.query_raw(id, "burn", &[], 0, GAS_LIMIT) {
+        Err(VMError::MethodNotFound { contract, method }) => {
+            assert_eq!(contract, id);
+            assert_eq!(method, "burn");
+        }
+        other => panic!("expected a missing method, got {:?}", other.map(|_| ())),
+    }
+
+    let huge = vec![0u8; MAX_ARGUMENT_SIZE + 1];
+    match state.query_raw(id, "total_supply", &huge, 0, GAS_LIMIT) {
+        Err(VMError::ArgumentTooLarge { len, .. }) => assert_eq!(len, huge.len()),
+        other => panic!("expected a too large argument, got {:?}", other.map(|_| ())),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn mint_emits_event() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), CODE)?;
+
+    let receipt = state.apply(id, &Mint { amount: 42 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    let events = receipt.events();
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].source(), id);
+    assert_eq!(events[0].topic(), "mint");
+    assert_eq!(events[0].decode::<u64>()?, 42);
+
+    Ok(())
+}
+
+#[test]
+fn state_root() -> Result<(), Box<dyn std::error::Error>> {
+    let mut a = State::default();
+    let mut b = State::default();
+
+    let id = a.deploy(Plutocracy::new(), CODE)?;
+    b.deploy(Plutocracy::new(), CODE)?;
+
+    assert_eq!(a.root(), b.root());
+
+    a.apply(id, &Mint { amount: 10 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_ne!(a.root(), b.root());
+
+    b.apply(id, &Mint { amount: 10 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(a.root(), b.root());
+
+    Ok(())
+}
+
+#[test]
+fn upgrade_without_migration() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), CODE)?;
+
+    state.apply(id, &Mint { amount: 7 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    state.upgrade(id, CODE, GAS_LIMIT)?;
+
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 7);
+
+    Ok(())
+}
+
+#[test]
+fn persist_and_reopen() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("pluto-persist-{}", std::process::id()));
+
+    let id = {
+        let mut state = State::open(&dir)?;
+        let id = state.deploy(Plutocracy::new(), CODE)?;
+        state.apply(id, &Mint { amount: 21 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+        state.persist()?;
+        id
+    };
+
+    let state = State::open(&dir)?;
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 21);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn gas_metering() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let id = state.deploy(Plutocracy::new(), CODE)?;
+
+    let receipt = state.apply(id, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert!(receipt.gas_used() > 0);
+    assert!(receipt.gas_used() <= GAS_LIMIT);
+
+    match state.apply(id, &Mint { amount: 100 }, CALLER, 0, BLOCK, 1) {
+        Err(VMError::OutOfGas) => (),
+        other => panic!("expected out of gas, got {:?}", other),
+    }
+
+    // the state is left untouched by the failed call
+    assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 100);
+
+    Ok(())
+}
+
+#[test]
+fn transaction_commit_and_rollback() -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = State::default();
+    let a = state.deploy(Plutocracy::new(), CODE)?;
+    let b = state.deploy(Plutocracy::new(), CODE)?;
+
+    let mut transaction = state.transaction();
+    transaction.apply(a, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    transaction.apply(b, &Mint { amount: 10 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert_eq!(*transaction.query(a, &TotalSupply, GAS_LIMIT)?, 100);
+    transaction.rollback();
+
+    assert_eq!(*state.query(a, &TotalSupply, GAS_LIMIT)?, 0);
+    assert_eq!(*state.query(b, &TotalSupply, GAS_LIMIT)?, 0);
+
+    let mut transaction = state.transaction();
+    transaction.apply(a, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    assert!(transaction
+        .apply(b, &Mint { amount: 10 }, CALLER, 0, BLOCK, 1)
+        .is_err());
+    transaction.commit();
+
+    assert_eq!(*state.query(a, &TotalSupply, GAS_LIMIT)?, 100);
+    assert_eq!(*state.query(b, &TotalSupply, GAS_LIMIT)?, 0);
+
+    {
+        // dropping a transaction discards its changes
+        let mut transaction = state.transaction();
+        transaction.apply(a, &Mint { amount: 100 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+    }
+
+    assert_eq!(*state.query(a, &TotalSupply, GAS_LIMIT)?, 100);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "wasmer-backend", feature = "wasmi-backend"))]
+fn backends_agree() -> Result<(), Box<dyn std::error::Error>> {
+    let mut wasmer = State::builder().backend(Wasmer::default()).build()?;
+    let mut wasmi = State::builder().backend(Wasmi::default()).build()?;
+
+    for state in [&mut wasmer, &mut wasmi] {
+        let id = state.deploy(Plutocracy::new(), CODE)?;
+        let receipt = state.apply(id, &Mint { amount: 12 }, CALLER, 0, BLOCK, GAS_LIMIT)?;
+        assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 12);
 
-    state.apply(id, &Mint { amount: 100 })?;
+        // the backends charge differently, but each runs a call given just
+        // the gas it costs there, and none with any less
+        let gas = receipt.gas_used();
+        state.apply(id, &Mint { amount: 1 }, CALLER, 0, BLOCK, gas)?;
+        match state.apply(id, &Mint { amount: 1 }, CALLER, 0, BLOCK, gas - 1) {
+            Err(VMError::OutOfGas) => (),
+            other => panic!("expected to run out of gas, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(*state.query(id, &TotalSupply, GAS_LIMIT)?, 13);
+    }
 
-    assert_eq!(state.query(id, &TotalSupply).unwrap(), 100);
+    assert_eq!(wasmer.root(), wasmi.root());
 
     Ok(())
 }